@@ -0,0 +1,209 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    encoder::EventEncoder, error::Error, handle_event, sink::EventSink, ChangeType, EventConfig,
+    Resource, ResourceChange, ResourceConfig,
+};
+
+const USAGE: &str =
+    "usage: --backfill [--ids-file <path|->] [--rate <ids per second>] [--checkpoint <path>]";
+
+/// Command line options for republishing resources from the harvester.
+#[derive(Debug)]
+pub struct BackfillArgs {
+    /// File with one id per line, `-` reads from stdin. Ids are listed from the harvester if absent.
+    pub ids_file: Option<String>,
+    /// Maximum number of resources published per second.
+    pub rate: f64,
+    /// File where published ids are recorded, ids already in it are skipped.
+    pub checkpoint: Option<String>,
+}
+
+impl BackfillArgs {
+    /// Parses backfill options, returns `None` unless `--backfill` is given.
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Option<Self>, Error> {
+        let mut backfill = false;
+        let mut backfill_args = BackfillArgs {
+            ids_file: None,
+            rate: 10.0,
+            checkpoint: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--backfill" => backfill = true,
+                "--ids-file" => backfill_args.ids_file = Some(args.next().ok_or(USAGE)?),
+                "--rate" => {
+                    backfill_args.rate = args
+                        .next()
+                        .and_then(|rate| rate.parse().ok())
+                        .filter(|rate| *rate > 0.0)
+                        .ok_or(USAGE)?
+                }
+                "--checkpoint" => backfill_args.checkpoint = Some(args.next().ok_or(USAGE)?),
+                _ => return Err(format!("unknown argument '{}', {}", arg, USAGE).into()),
+            }
+        }
+
+        Ok(backfill.then_some(backfill_args))
+    }
+}
+
+fn read_ids(ids_file: &str) -> Result<Vec<String>, Error> {
+    let lines = if ids_file == "-" {
+        io::stdin().lock().lines().collect::<Result<Vec<_>, _>>()
+    } else {
+        fs::read_to_string(ids_file).map(|content| content.lines().map(String::from).collect())
+    }
+    .map_err(|e| format!("unable to read ids from '{}': {}", ids_file, e))?;
+
+    Ok(lines
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect())
+}
+
+fn read_checkpoint(checkpoint: &str) -> Result<HashSet<String>, Error> {
+    match fs::read_to_string(checkpoint) {
+        Ok(content) => Ok(content.lines().map(String::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(format!("unable to read checkpoint '{}': {}", checkpoint, e).into()),
+    }
+}
+
+/// Publishes a harvest event for every resource, using the same pipeline as harvest reports.
+pub async fn run_backfill<R: Resource>(
+    sink: &dyn EventSink,
+    encoder: &EventEncoder,
+    resource_config: &ResourceConfig,
+    event_config: &EventConfig,
+    args: BackfillArgs,
+) -> Result<(), Error> {
+    let routing_key = resource_config
+        .routing_keys
+        .first()
+        .ok_or("no routing key configured")?;
+
+    let ids = match &args.ids_file {
+        Some(ids_file) => read_ids(ids_file)?,
+        None => R::resource_ids().await?,
+    };
+    let completed = match &args.checkpoint {
+        Some(checkpoint) => read_checkpoint(checkpoint)?,
+        None => HashSet::new(),
+    };
+    let mut checkpoint = match &args.checkpoint {
+        Some(checkpoint) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(checkpoint)
+                .map_err(|e| format!("unable to open checkpoint '{}': {}", checkpoint, e))?,
+        ),
+        None => None,
+    };
+
+    let remaining: Vec<String> = ids
+        .into_iter()
+        .filter(|id| !completed.contains(id))
+        .collect();
+    tracing::info!(
+        routing_key,
+        total = remaining.len() + completed.len(),
+        already_completed = completed.len(),
+        rate = args.rate,
+        "starting backfill"
+    );
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let total = remaining.len();
+    let mut failed = 0;
+    for (index, id) in remaining.into_iter().enumerate() {
+        interval.tick().await;
+
+        match handle_event::<R>(
+            encoder,
+            sink,
+            event_config,
+            routing_key,
+            ResourceChange {
+                id: id.clone(),
+                change: ChangeType::CreateOrUpdate,
+                timestamp,
+            },
+            false,
+        )
+        .await
+        {
+            Ok(_) => {
+                if let Some(checkpoint) = checkpoint.as_mut() {
+                    writeln!(checkpoint, "{}", id)
+                        .map_err(|e| format!("unable to write checkpoint: {}", e))?;
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                tracing::error!(id, error = e.to_string(), "failed to backfill resource");
+            }
+        }
+
+        let processed = index + 1;
+        if processed % 100 == 0 || processed == total {
+            tracing::info!(processed, total, failed, "backfill progress");
+        }
+    }
+
+    tracing::info!(total, failed, "backfill finished");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<BackfillArgs>, Error> {
+        BackfillArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_backfill_without_flag() {
+        assert!(parse(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_backfill_options() {
+        let args = parse(&[
+            "--backfill",
+            "--ids-file",
+            "ids.txt",
+            "--rate",
+            "2.5",
+            "--checkpoint",
+            "done.txt",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.ids_file.as_deref(), Some("ids.txt"));
+        assert_eq!(args.rate, 2.5);
+        assert_eq!(args.checkpoint.as_deref(), Some("done.txt"));
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(parse(&["--backfill", "--rate", "0"]).is_err());
+        assert!(parse(&["--backfill", "--ids-file"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
@@ -5,8 +5,9 @@ use lazy_static::lazy_static;
 use serde_derive::Serialize;
 
 use fdk_kafka_event_publisher::{
-    error::Error, kafka, run_event_publisher, utils::http_get, ChangeType, EventConfig, Resource,
-    ResourceConfig,
+    error::Error, kafka, run_event_publisher, telemetry::init_tracing,
+    utils::{http_get, list_ids, owner_ids, removed_owner_ids},
+    ChangeType, EventConfig, RemovalScope, Resource, ResourceConfig,
 };
 
 lazy_static! {
@@ -18,16 +19,8 @@ lazy_static! {
         env::var("OUTPUT_TOPIC").unwrap_or("dataset-events".to_string());
 }
 
-#[tokio::main]
-async fn main() {
-    let resource_config = ResourceConfig {
-        consumer_name: CONSUMER_NAME.clone(),
-        routing_keys: vec![
-            "datasets.harvested".to_string(),
-        ],
-    };
-
-    let event_config = EventConfig {
+fn event_config() -> EventConfig {
+    EventConfig {
         name: "no.fdk.dataset.DatasetEvent".to_string(),
         topic: OUTPUT_TOPIC.clone(),
         schema: r#"{
@@ -49,14 +42,25 @@ async fn main() {
                 ]
             }"#
         .to_string(),
+        subject_strategy: *kafka::SUBJECT_STRATEGY,
+        format: *kafka::EVENT_FORMAT,
+        tombstones: *kafka::TOMBSTONE_MODE,
+        snapshot_topic: kafka::SNAPSHOT_TOPIC.clone(),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let resource_config = ResourceConfig {
+        consumer_name: CONSUMER_NAME.clone(),
+        routing_keys: vec![
+            "datasets.harvested".to_string(),
+        ],
     };
 
-    tracing_subscriber::fmt()
-        .json()
-        .with_max_level(tracing::Level::INFO)
-        .with_target(false)
-        .with_current_span(false)
-        .init();
+    let event_config = event_config();
+
+    init_tracing(&CONSUMER_NAME);
 
     run_event_publisher::<Dataset>(resource_config, event_config).await
 }
@@ -87,13 +91,35 @@ impl Resource for Dataset {
             DatasetEventType::DatasetRemoved => Ok("".to_string()),
         }?;
 
+        let owner_ids = match event_type {
+            DatasetEventType::DatasetRemoved => {
+                removed_owner_ids(format!("{}/datasets/{}?catalogrecords=true", HARVESTER_API_URL.as_str(), id), &id).await
+            }
+            _ => Ok(owner_ids(&graph, &id)),
+        }?;
+
         Ok(Some(Self::Event {
             event_type,
             fdk_id: id,
             graph,
             timestamp,
+            owner_ids,
         }))
     }
+
+    async fn resource_ids() -> Result<Vec<String>, Error> {
+        list_ids(format!("{}/datasets/ids", HARVESTER_API_URL.as_str())).await
+    }
+
+    async fn removed_ids(scope: RemovalScope, id: &str) -> Result<Vec<String>, Error> {
+        list_ids(format!(
+            "{}/datasets/ids?{}={}",
+            HARVESTER_API_URL.as_str(),
+            scope.query_param(),
+            id
+        ))
+        .await
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -104,12 +130,22 @@ pub struct DatasetEvent {
     pub fdk_id: String,
     pub graph: String,
     pub timestamp: i64,
+    #[serde(skip)]
+    pub owner_ids: kafka::OwnerIds,
 }
 
 impl kafka::Event for DatasetEvent {
     fn key(&self) -> String {
         self.fdk_id.clone()
     }
+
+    fn owner_ids(&self) -> kafka::OwnerIds {
+        self.owner_ids.clone()
+    }
+
+    fn is_removal(&self) -> bool {
+        matches!(self.event_type, DatasetEventType::DatasetRemoved)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize)]
@@ -134,3 +170,86 @@ impl DatasetEventType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fdk_kafka_event_publisher::{
+        source::Acknowledgement,
+        testing::{report, stub_get, TestHarness},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn harvested_resource_is_published_with_graph() {
+        let harness = TestHarness::new(event_config());
+        stub_get("/datasets/harvested-1?catalogrecords=true", 200, "<graph>");
+
+        let ack = harness
+            .deliver::<Dataset>("datasets.harvested", &report(&["harvested-1"], &[]))
+            .await;
+        assert_eq!(ack, Acknowledgement::Ack);
+
+        let records = harness.records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, *OUTPUT_TOPIC);
+        assert_eq!(records[0].key.as_deref(), Some("harvested-1"));
+        assert_eq!(records[0].value["type"], "DATASET_HARVESTED");
+        assert_eq!(records[0].value["fdkId"], "harvested-1");
+        assert_eq!(records[0].value["graph"], "<graph>");
+        assert_eq!(records[0].value["timestamp"], 1644575406110i64);
+    }
+
+    #[tokio::test]
+    async fn removed_resource_is_published_without_graph() {
+        let harness = TestHarness::new(event_config());
+
+        let ack = harness
+            .deliver::<Dataset>("datasets.harvested", &report(&[], &["removed-1"]))
+            .await;
+        assert_eq!(ack, Acknowledgement::Ack);
+
+        let records = harness.records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value["type"], "DATASET_REMOVED");
+        assert_eq!(records[0].value["graph"], "");
+    }
+
+    #[tokio::test]
+    async fn reasoned_and_unknown_routing_keys_are_not_published() {
+        let harness = TestHarness::new(event_config());
+        stub_get("/datasets/reasoned-1?catalogrecords=true", 200, "<graph>");
+
+        for routing_key in ["datasets.reasoned", "unknown.harvested"] {
+            let ack = harness
+                .deliver::<Dataset>(routing_key, &report(&["reasoned-1"], &[]))
+                .await;
+            assert_eq!(ack, Acknowledgement::Ack);
+        }
+        assert!(harness.raw_records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn removed_ids_are_listed_by_scope() {
+        stub_get("/datasets/ids?catalogId=catalog-1", 200, r#"["catalog-resource-1"]"#);
+        stub_get("/datasets/ids?dataSourceId=source-1", 200, r#"["source-resource-1"]"#);
+
+        assert_eq!(
+            Dataset::removed_ids(RemovalScope::Catalog, "catalog-1").await.unwrap(),
+            ["catalog-resource-1"]
+        );
+        assert_eq!(
+            Dataset::removed_ids(RemovalScope::DataSource, "source-1").await.unwrap(),
+            ["source-resource-1"]
+        );
+    }
+
+    #[test]
+    fn event_type_from_routing_key() {
+        assert!(matches!(
+            DatasetEventType::from_routing_key("datasets.harvested"),
+            Ok(DatasetEventType::DatasetHarvested)
+        ));
+        assert!(DatasetEventType::from_routing_key("datasets.unknown").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    env,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use tokio::sync::Notify;
+
+use crate::metrics::{CIRCUIT_BREAKER_REJECTIONS, CIRCUIT_BREAKER_STATE};
+
+lazy_static! {
+    pub static ref CIRCUIT_BREAKER_FAILURE_THRESHOLD: u32 =
+        env::var("CIRCUIT_BREAKER_FAILURE_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(5);
+    pub static ref CIRCUIT_BREAKER_OPEN_MS: u64 = env::var("CIRCUIT_BREAKER_OPEN_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30000);
+    pub static ref HARVESTER_CIRCUIT: CircuitBreaker = CircuitBreaker::new(
+        "harvester",
+        *CIRCUIT_BREAKER_FAILURE_THRESHOLD,
+        Duration::from_millis(*CIRCUIT_BREAKER_OPEN_MS)
+    );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CircuitState {
+    /// Calls are made.
+    Closed,
+    /// Calls fail without being made.
+    Open,
+    /// A single probe call is made, closing the circuit if it succeeds.
+    HalfOpen,
+}
+
+impl CircuitState {
+    fn metric_value(&self) -> i64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    /// When the circuit opened, or when the last probe was let through.
+    changed_at: Instant,
+}
+
+/// Fails calls to a service without making them after consecutive failures, letting a probe
+/// through once the circuit has been open for `open_duration`.
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    circuit: Mutex<Circuit>,
+    /// Wakes callers waiting for a probe when the circuit opens or closes.
+    changed: Notify,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, open_duration: Duration) -> Self {
+        CIRCUIT_BREAKER_STATE
+            .with_label_values(&[name])
+            .set(CircuitState::Closed.metric_value());
+        Self {
+            name,
+            failure_threshold,
+            open_duration,
+            circuit: Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                changed_at: Instant::now(),
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.lock().state
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_state(&self, circuit: &mut Circuit, state: CircuitState) {
+        circuit.state = state;
+        circuit.changed_at = Instant::now();
+        CIRCUIT_BREAKER_STATE
+            .with_label_values(&[self.name])
+            .set(state.metric_value());
+        self.changed.notify_waiters();
+    }
+
+    /// Whether a call may be made. A probe is let through when the circuit has been open, or the
+    /// last probe has been in flight, for the open duration.
+    pub fn allow(&self) -> bool {
+        let mut circuit = self.lock();
+        match circuit.state {
+            CircuitState::Closed => true,
+            _ if circuit.changed_at.elapsed() >= self.open_duration => {
+                tracing::info!(circuit = self.name, "circuit half-open, probing");
+                self.set_state(&mut circuit, CircuitState::HalfOpen);
+                true
+            }
+            _ => {
+                CIRCUIT_BREAKER_REJECTIONS
+                    .with_label_values(&[self.name])
+                    .inc();
+                false
+            }
+        }
+    }
+
+    pub fn success(&self) {
+        let mut circuit = self.lock();
+        circuit.consecutive_failures = 0;
+        if circuit.state != CircuitState::Closed {
+            tracing::info!(circuit = self.name, "circuit closed");
+            self.set_state(&mut circuit, CircuitState::Closed);
+        }
+    }
+
+    pub fn failure(&self) {
+        let mut circuit = self.lock();
+        circuit.consecutive_failures += 1;
+        let open = match circuit.state {
+            CircuitState::Closed => circuit.consecutive_failures >= self.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if open {
+            tracing::warn!(
+                circuit = self.name,
+                consecutive_failures = circuit.consecutive_failures,
+                "circuit opened"
+            );
+            self.set_state(&mut circuit, CircuitState::Open);
+        }
+    }
+
+    /// Time until a probe is let through, `None` when the circuit is closed. While half-open this
+    /// is the time until another probe is let through, should the current one not report back.
+    pub fn probe_delay(&self) -> Option<Duration> {
+        let circuit = self.lock();
+        match circuit.state {
+            CircuitState::Open | CircuitState::HalfOpen => Some(
+                self.open_duration
+                    .saturating_sub(circuit.changed_at.elapsed()),
+            ),
+            CircuitState::Closed => None,
+        }
+        .filter(|delay| !delay.is_zero())
+    }
+
+    /// Waits until a probe is due, or the in-flight probe closes the circuit.
+    pub async fn wait_for_probe(&self) {
+        loop {
+            // Created before checking the state, so a change in between is not missed
+            let changed = self.changed.notified();
+            let Some(delay) = self.probe_delay() else {
+                return;
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => return,
+                _ = changed => {}
+            }
+        }
+    }
+}
+
+/// The circuits of the services the publisher depends on.
+pub fn circuits() -> [&'static CircuitBreaker; 1] {
+    [&HARVESTER_CIRCUIT]
+}
+
+/// Time until the open and half-open circuits let a probe through, `None` when calls may be made.
+pub fn probe_delay() -> Option<Duration> {
+    circuits()
+        .iter()
+        .filter_map(|circuit| circuit.probe_delay())
+        .max()
+}
+
+/// Waits until every circuit is closed or due a probe.
+pub async fn wait_for_probes() {
+    for circuit in circuits() {
+        circuit.wait_for_probe().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let circuit = CircuitBreaker::new("test", 2, Duration::from_secs(60));
+        circuit.failure();
+        circuit.success();
+        circuit.failure();
+        assert!(circuit.allow());
+        circuit.failure();
+
+        assert_eq!(circuit.state(), CircuitState::Open);
+        assert!(!circuit.allow());
+        assert!(circuit.probe_delay().is_some());
+    }
+
+    #[test]
+    fn probe_closes_or_reopens_circuit() {
+        let circuit = CircuitBreaker::new("test", 1, Duration::from_millis(10));
+        circuit.failure();
+        assert!(!circuit.allow());
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(circuit.probe_delay(), None);
+        assert!(circuit.allow());
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.probe_delay().is_some());
+        assert!(!circuit.allow());
+        circuit.failure();
+        assert_eq!(circuit.state(), CircuitState::Open);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(circuit.allow());
+        circuit.success();
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.allow());
+    }
+
+    #[tokio::test]
+    async fn waits_for_probe_while_half_open() {
+        let circuit = CircuitBreaker::new("test", 1, Duration::from_secs(60));
+        circuit.failure();
+        assert!(circuit.probe_delay().is_some());
+
+        let waiter = circuit.wait_for_probe();
+        tokio::pin!(waiter);
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut waiter)
+            .await
+            .is_err());
+
+        circuit.success();
+        tokio::time::timeout(Duration::from_millis(10), waiter)
+            .await
+            .unwrap();
+    }
+}
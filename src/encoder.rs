@@ -0,0 +1,182 @@
+use apache_avro::Schema;
+use protobuf::{
+    reflect::{MessageDescriptor, ReflectValueBox, RuntimeFieldType, RuntimeType},
+    MessageDyn,
+};
+use schema_registry_converter::schema_registry_common::get_payload;
+use serde::Serialize;
+
+use crate::{
+    kafka::{EventFormat, KafkaError},
+    schema::proto_descriptor,
+    EventConfig,
+};
+
+/// Parsed schema used to encode events.
+enum EventSchema {
+    Avro(Schema),
+    Json,
+    Protobuf(MessageDescriptor),
+}
+
+/// Encodes events with the local schema, framed with the schema id as expected by Confluent
+/// deserializers. One encoder is shared by all deliveries, the schema id is resolved once at
+/// startup, so encoding never calls the schema registry.
+pub struct EventEncoder {
+    schema: EventSchema,
+    schema_id: u32,
+}
+
+impl EventEncoder {
+    pub fn new(event_config: &EventConfig, schema_id: u32) -> Result<Self, KafkaError> {
+        let schema = match event_config.format {
+            EventFormat::Avro => EventSchema::Avro(
+                Schema::parse_str(&event_config.schema)
+                    .map_err(|e| KafkaError::SchemaError(e.to_string()))?,
+            ),
+            EventFormat::Json => EventSchema::Json,
+            EventFormat::Protobuf => EventSchema::Protobuf(proto_descriptor(event_config)?),
+        };
+        Ok(Self { schema, schema_id })
+    }
+
+    /// Encodes an event, `value` is the event serialized to JSON.
+    pub fn encode<E: Serialize>(
+        &self,
+        event: E,
+        value: &serde_json::Value,
+    ) -> Result<Vec<u8>, KafkaError> {
+        let bytes = match &self.schema {
+            EventSchema::Avro(schema) => {
+                let value = apache_avro::to_value(event)
+                    .map_err(|e| KafkaError::SchemaError(e.to_string()))?
+                    .resolve(schema)
+                    .map_err(|e| KafkaError::SchemaError(e.to_string()))?;
+                apache_avro::to_avro_datum(schema, value)
+                    .map_err(|e| KafkaError::SchemaError(e.to_string()))?
+            }
+            EventSchema::Json => serde_json::to_vec(value)?,
+            EventSchema::Protobuf(descriptor) => {
+                // Message indexes, the event is the first message in the schema
+                let mut bytes = vec![0];
+                bytes.extend(json_to_message(value, descriptor)?.write_to_bytes_dyn()?);
+                bytes
+            }
+        };
+        Ok(get_payload(self.schema_id, bytes))
+    }
+}
+
+fn unsupported_field(name: &str) -> KafkaError {
+    KafkaError::SchemaError(format!("unsupported value in protobuf field '{}'", name))
+}
+
+/// Builds a protobuf message from an event serialized to JSON, null and missing fields are unset.
+pub(crate) fn json_to_message(
+    value: &serde_json::Value,
+    descriptor: &MessageDescriptor,
+) -> Result<Box<dyn MessageDyn>, KafkaError> {
+    let mut message = descriptor.new_instance();
+    for field in descriptor.fields() {
+        let value = match value.get(field.name()) {
+            None | Some(serde_json::Value::Null) => continue,
+            Some(value) => value,
+        };
+        let runtime_type = match field.runtime_field_type() {
+            RuntimeFieldType::Singular(runtime_type) => runtime_type,
+            _ => return Err(unsupported_field(field.name())),
+        };
+
+        let reflect_value = match runtime_type {
+            RuntimeType::Bool => value.as_bool().map(ReflectValueBox::Bool),
+            RuntimeType::I32 => value
+                .as_i64()
+                .and_then(|value| i32::try_from(value).ok())
+                .map(ReflectValueBox::I32),
+            RuntimeType::I64 => value.as_i64().map(ReflectValueBox::I64),
+            RuntimeType::F32 => value
+                .as_f64()
+                .map(|value| ReflectValueBox::F32(value as f32)),
+            RuntimeType::F64 => value.as_f64().map(ReflectValueBox::F64),
+            RuntimeType::String => value
+                .as_str()
+                .map(|value| ReflectValueBox::String(value.to_string())),
+            RuntimeType::Enum(descriptor) => value
+                .as_str()
+                .and_then(|symbol| descriptor.value_by_name(symbol))
+                .map(|value| ReflectValueBox::Enum(descriptor.clone(), value.value())),
+            _ => None,
+        }
+        .ok_or_else(|| unsupported_field(field.name()))?;
+        field.set_singular_field(message.as_mut(), reflect_value);
+    }
+    Ok(message)
+}
+
+/// Converts a protobuf message to JSON, the inverse of `json_to_message`.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn message_to_json(message: &dyn MessageDyn) -> serde_json::Value {
+    use protobuf::reflect::ReflectValueRef;
+
+    let descriptor = message.descriptor_dyn();
+    let fields = descriptor
+        .fields()
+        .map(|field| {
+            if field.proto().proto3_optional() && !field.has_field(message) {
+                return (field.name().to_string(), serde_json::Value::Null);
+            }
+            let value = match field.get_singular_field_or_default(message) {
+                ReflectValueRef::Bool(value) => serde_json::json!(value),
+                ReflectValueRef::I32(value) => serde_json::json!(value),
+                ReflectValueRef::I64(value) => serde_json::json!(value),
+                ReflectValueRef::F32(value) => serde_json::json!(value),
+                ReflectValueRef::F64(value) => serde_json::json!(value),
+                ReflectValueRef::String(value) => serde_json::json!(value),
+                ReflectValueRef::Enum(descriptor, number) => serde_json::json!(descriptor
+                    .value_by_number(number)
+                    .map(|value| value.name().to_string())),
+                _ => serde_json::Value::Null,
+            };
+            (field.name().to_string(), value)
+        })
+        .collect();
+    serde_json::Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kafka::{SubjectStrategy, TombstoneMode};
+
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "name": "TestEvent",
+        "namespace": "no.fdk.test",
+        "type": "record",
+        "fields": [{"name": "fdkId", "type": "string"}]
+    }"#;
+
+    fn event_config(format: EventFormat) -> EventConfig {
+        EventConfig {
+            name: "no.fdk.test.TestEvent".to_string(),
+            topic: "test-events".to_string(),
+            schema: SCHEMA.to_string(),
+            subject_strategy: SubjectStrategy::TopicName,
+            format,
+            tombstones: TombstoneMode::None,
+            snapshot_topic: None,
+        }
+    }
+
+    #[test]
+    fn frames_payload_with_schema_id() {
+        let value = serde_json::json!({"fdkId": "123"});
+        for format in [EventFormat::Avro, EventFormat::Json, EventFormat::Protobuf] {
+            let encoder = EventEncoder::new(&event_config(format), 42).unwrap();
+            let payload = encoder.encode(&value, &value).unwrap();
+
+            assert_eq!(payload[0], 0);
+            assert_eq!(u32::from_be_bytes(payload[1..5].try_into().unwrap()), 42);
+        }
+    }
+}
@@ -5,6 +5,8 @@ pub enum Error {
     #[error(transparent)]
     RabbitError(#[from] crate::rabbit::RabbitError),
     #[error(transparent)]
+    HarvesterError(#[from] crate::harvester::HarvesterError),
+    #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
     #[error(transparent)]
     SerdeError(#[from] serde_json::Error),
@@ -14,6 +16,30 @@ pub enum Error {
     String(String),
 }
 
+impl Error {
+    /// Whether the error is from a call rejected by an open circuit.
+    pub fn is_circuit_open(&self) -> bool {
+        matches!(
+            self,
+            Error::HarvesterError(crate::harvester::HarvesterError::CircuitOpen(_))
+        )
+    }
+
+    /// Whether the error may not recur if the call is made again later, as when the harvester is
+    /// unavailable.
+    pub fn is_transient(&self) -> bool {
+        self.is_circuit_open()
+            || matches!(
+                self,
+                Error::HarvesterError(
+                    crate::harvester::HarvesterError::ServerError(..)
+                        | crate::harvester::HarvesterError::Timeout(_)
+                        | crate::harvester::HarvesterError::ReqwestError(_)
+                )
+            )
+    }
+}
+
 impl From<&str> for Error {
     fn from(e: &str) -> Self {
         Self::String(e.to_string())
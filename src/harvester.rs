@@ -0,0 +1,330 @@
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+use tracing::Instrument;
+
+use crate::{
+    circuit::HARVESTER_CIRCUIT,
+    metrics::{GRAPH_SIZE, HARVESTER_FETCH_TIME, HARVESTER_WAIT_TIME},
+};
+
+lazy_static! {
+    pub static ref HARVESTER_CONNECT_TIMEOUT_MS: u64 = env::var("HARVESTER_CONNECT_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5000);
+    pub static ref HARVESTER_REQUEST_TIMEOUT_MS: u64 = env::var("HARVESTER_REQUEST_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60000);
+    pub static ref HARVESTER_PROXY: Option<String> = env::var("HARVESTER_PROXY").ok();
+    pub static ref HARVESTER_API_KEY: Option<String> = env::var("HARVESTER_API_KEY").ok();
+    pub static ref HARVESTER_OAUTH2_TOKEN_URL: Option<String> =
+        env::var("HARVESTER_OAUTH2_TOKEN_URL").ok();
+    pub static ref HARVESTER_OAUTH2_CLIENT_ID: String =
+        env::var("HARVESTER_OAUTH2_CLIENT_ID").unwrap_or_default();
+    pub static ref HARVESTER_OAUTH2_CLIENT_SECRET: String =
+        env::var("HARVESTER_OAUTH2_CLIENT_SECRET").unwrap_or_default();
+    pub static ref HARVESTER_OAUTH2_SCOPE: Option<String> = env::var("HARVESTER_OAUTH2_SCOPE").ok();
+    pub static ref NOT_FOUND_POLICY: NotFoundPolicy = env::var("HARVESTER_NOT_FOUND_POLICY")
+        .map(|policy| NotFoundPolicy::from_name(&policy))
+        .unwrap_or(Ok(NotFoundPolicy::Skip))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "harvester not found policy error");
+            std::process::exit(1);
+        });
+    pub static ref NOT_FOUND_RETRIES: u32 = env::var("HARVESTER_NOT_FOUND_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3);
+    pub static ref NOT_FOUND_RETRY_DELAY_MS: u64 = env::var("HARVESTER_NOT_FOUND_RETRY_DELAY_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5000);
+    pub static ref HARVESTER_RATE_LIMIT: Option<f64> = env::var("HARVESTER_RATE_LIMIT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|rate| *rate > 0.0);
+    pub static ref HARVESTER_RATE_BURST: f64 = env::var("HARVESTER_RATE_BURST")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| HARVESTER_RATE_LIMIT.unwrap_or(1.0).max(1.0));
+    pub static ref HARVESTER_MAX_CONCURRENT_REQUESTS: Option<usize> =
+        env::var("HARVESTER_MAX_CONCURRENT_REQUESTS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|max| *max > 0);
+    static ref RATE_LIMITER: Option<TokenBucket> =
+        HARVESTER_RATE_LIMIT.map(|rate| TokenBucket::new(rate, *HARVESTER_RATE_BURST));
+    static ref CONCURRENCY_LIMITER: Option<Semaphore> =
+        HARVESTER_MAX_CONCURRENT_REQUESTS.map(Semaphore::new);
+    static ref CLIENT: Client = create_client().unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "harvester client creation error");
+        std::process::exit(1);
+    });
+    static ref TOKEN: Mutex<Option<AccessToken>> = Mutex::new(None);
+}
+
+/// Tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HarvesterError {
+    #[error("resource not found: {0}")]
+    NotFound(String),
+    #[error("harvester server error: {0} - {1}")]
+    ServerError(StatusCode, String),
+    #[error("unexpected harvester response: {0} - {1}")]
+    UnexpectedResponse(StatusCode, String),
+    #[error("harvester request timed out: {0}")]
+    Timeout(String),
+    #[error("harvester authentication failed: {0}")]
+    AuthError(String),
+    #[error("harvester circuit open: {0}")]
+    CircuitOpen(String),
+    #[error(transparent)]
+    ReqwestError(#[from] reqwest::Error),
+}
+
+/// How to handle a changed resource that the harvester no longer has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotFoundPolicy {
+    /// Publish a removal event for the resource.
+    Remove,
+    /// Skip the resource.
+    Skip,
+    /// Fetch the resource again after the rest of the delivery, skipping it when retries are
+    /// exhausted.
+    Retry,
+}
+
+impl NotFoundPolicy {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "remove" => Ok(Self::Remove),
+            "skip" => Ok(Self::Skip),
+            "retry" => Ok(Self::Retry),
+            _ => Err(format!("unknown not found policy: '{}'", name)),
+        }
+    }
+}
+
+/// Token bucket allowing `rate` requests per second, with bursts of up to `burst` requests.
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, tokio::time::Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, tokio::time::Instant::now())),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let (tokens, refilled_at) = *state;
+                let now = tokio::time::Instant::now();
+                let tokens = (tokens + now.duration_since(refilled_at).as_secs_f64() * self.rate)
+                    .min(self.burst);
+                if tokens >= 1.0 {
+                    *state = (tokens - 1.0, now);
+                    return;
+                }
+                *state = (tokens, now);
+                Duration::from_secs_f64((1.0 - tokens) / self.rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Waits for the rate and concurrency limits shared by all harvester requests in the process. The
+/// returned permit must be held until the response is read.
+async fn wait_for_limits() -> Option<SemaphorePermit<'static>> {
+    let start_time = Instant::now();
+    let permit = match CONCURRENCY_LIMITER.as_ref() {
+        Some(semaphore) => semaphore.acquire().await.ok(),
+        None => None,
+    };
+    if let Some(rate_limiter) = RATE_LIMITER.as_ref() {
+        rate_limiter.acquire().await;
+    }
+    HARVESTER_WAIT_TIME.observe(start_time.elapsed().as_secs_f64());
+    permit
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+fn create_client() -> Result<Client, HarvesterError> {
+    let mut builder = Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .connect_timeout(Duration::from_millis(*HARVESTER_CONNECT_TIMEOUT_MS))
+        .timeout(Duration::from_millis(*HARVESTER_REQUEST_TIMEOUT_MS));
+
+    if let Some(proxy) = HARVESTER_PROXY.as_ref() {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Adds the configured API key or OAuth2 bearer token to a request.
+async fn authorize(request: RequestBuilder) -> Result<RequestBuilder, HarvesterError> {
+    let request = match HARVESTER_API_KEY.as_ref() {
+        Some(api_key) => request.header("X-API-KEY", api_key),
+        None => request,
+    };
+
+    match HARVESTER_OAUTH2_TOKEN_URL.as_ref() {
+        Some(token_url) => Ok(request.bearer_auth(access_token(token_url).await?)),
+        None => Ok(request),
+    }
+}
+
+/// Returns a cached client credentials token, fetching a new one when missing or about to expire.
+async fn access_token(token_url: &str) -> Result<String, HarvesterError> {
+    let mut cached = TOKEN.lock().await;
+    if let Some(token) = cached.as_ref() {
+        if token.expires_at > Instant::now() + TOKEN_EXPIRY_MARGIN {
+            return Ok(token.token.clone());
+        }
+    }
+
+    let mut form = vec![
+        ("grant_type", "client_credentials"),
+        ("client_id", HARVESTER_OAUTH2_CLIENT_ID.as_str()),
+        ("client_secret", HARVESTER_OAUTH2_CLIENT_SECRET.as_str()),
+    ];
+    if let Some(scope) = HARVESTER_OAUTH2_SCOPE.as_ref() {
+        form.push(("scope", scope.as_str()));
+    }
+
+    let response = CLIENT
+        .post(token_url)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| request_error(token_url, e))?;
+    let status = response.status();
+    let body = response.text().await?;
+    if status != StatusCode::OK {
+        return Err(HarvesterError::AuthError(format!("{} - {}", status, body)));
+    }
+
+    let response: TokenResponse = serde_json::from_str(&body)
+        .map_err(|e| HarvesterError::AuthError(format!("invalid token response: {}", e)))?;
+    let expires_in = Duration::from_secs(response.expires_in.unwrap_or(300));
+    *cached = Some(AccessToken {
+        token: response.access_token.clone(),
+        expires_at: Instant::now() + expires_in,
+    });
+
+    Ok(response.access_token)
+}
+
+fn request_error(url: &str, error: reqwest::Error) -> HarvesterError {
+    if error.is_timeout() {
+        HarvesterError::Timeout(url.to_string())
+    } else {
+        HarvesterError::ReqwestError(error)
+    }
+}
+
+/// Fetches a resource from the harvester API, failing without a request while the harvester
+/// circuit is open. Server errors, timeouts and connection errors count as circuit failures.
+pub async fn get(url: String) -> Result<String, HarvesterError> {
+    if !HARVESTER_CIRCUIT.allow() {
+        return Err(HarvesterError::CircuitOpen(url));
+    }
+
+    let result = fetch(url).await;
+    match &result {
+        Err(
+            HarvesterError::ServerError(..)
+            | HarvesterError::Timeout(_)
+            | HarvesterError::ReqwestError(_),
+        ) => HARVESTER_CIRCUIT.failure(),
+        _ => HARVESTER_CIRCUIT.success(),
+    }
+    result
+}
+
+async fn fetch(url: String) -> Result<String, HarvesterError> {
+    let span = tracing::info_span!("http_get", url = url.as_str());
+    let _permit = wait_for_limits().instrument(span.clone()).await;
+    let start_time = Instant::now();
+    let response = authorize(CLIENT.get(&url))
+        .await?
+        .send()
+        .instrument(span)
+        .await
+        .map_err(|e| request_error(&url, e))?;
+    HARVESTER_FETCH_TIME.observe(start_time.elapsed().as_secs_f64());
+
+    let status = response.status();
+    let body = response.text().await.map_err(|e| request_error(&url, e))?;
+    match status {
+        StatusCode::OK => {
+            GRAPH_SIZE.observe(body.len() as f64);
+            Ok(body)
+        }
+        StatusCode::NOT_FOUND => Err(HarvesterError::NotFound(url)),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            // Make sure a fresh token is used for the next request
+            TOKEN.lock().await.take();
+            Err(HarvesterError::AuthError(format!("{} - {}", status, body)))
+        }
+        status if status.is_server_error() => Err(HarvesterError::ServerError(status, body)),
+        status => Err(HarvesterError::UnexpectedResponse(status, body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_limits_rate_after_burst() {
+        let bucket = TokenBucket::new(50.0, 2.0);
+        let start_time = tokio::time::Instant::now();
+        for _ in 0..2 {
+            bucket.acquire().await;
+        }
+        assert_eq!(start_time.elapsed(), Duration::ZERO);
+
+        // The paused clock advances to each wakeup, so waits take exactly their time
+        for _ in 0..3 {
+            bucket.acquire().await;
+        }
+        let elapsed = start_time.elapsed();
+        assert!(elapsed >= Duration::from_millis(60));
+        assert!(elapsed < Duration::from_millis(80));
+    }
+}
@@ -1,15 +1,41 @@
-use actix_web::{get, App, HttpServer, Responder};
+use std::{env, sync::Arc};
 
-use crate::metrics::get_metrics;
+use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{
+    circuit::{circuits, CircuitState},
+    encoder::EventEncoder,
+    error::Error,
+    harvester::HarvesterError,
+    kafka::DeliveryReceipt,
+    metrics::get_metrics,
+    publish_event, ChangeType, EventConfig, Resource, ResourceConfig, SINK,
+};
+
+lazy_static! {
+    static ref ADMIN_API_KEY: Option<String> = env::var("ADMIN_API_KEY").ok();
+}
 
 #[get("/ping")]
 async fn ping() -> impl Responder {
     "pong"
 }
 
+/// Not ready while a circuit is open or half-open.
 #[get("/ready")]
 async fn ready() -> impl Responder {
-    "ok"
+    let open = circuits()
+        .iter()
+        .filter(|circuit| circuit.state() != CircuitState::Closed)
+        .map(|circuit| format!("{} circuit {:?}", circuit.name(), circuit.state()))
+        .collect::<Vec<_>>();
+
+    match open.is_empty() {
+        true => HttpResponse::Ok().body("ok"),
+        false => HttpResponse::ServiceUnavailable().body(open.join(", ")),
+    }
 }
 
 #[get("/metrics")]
@@ -23,13 +49,147 @@ async fn metrics_service() -> impl Responder {
     }
 }
 
+struct AdminState {
+    routing_key: String,
+    event_config: EventConfig,
+    encoder: Arc<EventEncoder>,
+}
+
+#[derive(Serialize)]
+struct AdminResponse {
+    #[serde(rename = "fdkId")]
+    fdk_id: String,
+    published: bool,
+    #[serde(flatten)]
+    receipt: Option<DeliveryReceipt>,
+}
+
+fn authorized(request: &HttpRequest) -> bool {
+    let api_key = request
+        .headers()
+        .get("X-API-KEY")
+        .and_then(|value| value.to_str().ok());
+
+    match (ADMIN_API_KEY.as_deref(), api_key) {
+        (Some(expected), Some(api_key)) => expected == api_key,
+        _ => false,
+    }
+}
+
+/// Publishes the event of a resource regardless of the not found policy, so publishing an unknown
+/// id fails rather than removing or skipping it.
+async fn publish_admin_event<R: Resource>(
+    state: &AdminState,
+    fdk_id: String,
+    timestamp: i64,
+    change: ChangeType,
+) -> Result<Option<DeliveryReceipt>, Error> {
+    match R::event(&state.routing_key, fdk_id.clone(), timestamp, change).await? {
+        Some(event) => Ok(Some(
+            publish_event(
+                &state.encoder,
+                SINK.as_ref(),
+                &state.event_config,
+                fdk_id,
+                timestamp,
+                event,
+            )
+            .await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+async fn admin_event<R: Resource>(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    fdk_id: String,
+    change: ChangeType,
+) -> HttpResponse {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    tracing::info!(
+        id = fdk_id,
+        change = format!("{:?}", change),
+        "handling admin request"
+    );
+
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    match publish_admin_event::<R>(&state, fdk_id.clone(), timestamp, change).await {
+        Ok(receipt) => HttpResponse::Ok().json(AdminResponse {
+            fdk_id,
+            published: receipt.is_some(),
+            receipt,
+        }),
+        Err(Error::HarvesterError(HarvesterError::NotFound(url))) => {
+            HttpResponse::NotFound().body(format!("resource not found: {}", url))
+        }
+        Err(e) if e.is_circuit_open() => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        Err(e) => {
+            tracing::error!(
+                id = fdk_id,
+                error = e.to_string(),
+                "failed while handling admin request"
+            );
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+async fn publish_resource<R: Resource>(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    admin_event::<R>(
+        request,
+        state,
+        path.into_inner(),
+        ChangeType::CreateOrUpdate,
+    )
+    .await
+}
+
+async fn remove_resource<R: Resource>(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    admin_event::<R>(request, state, path.into_inner(), ChangeType::Remove).await
+}
+
 // TODO: should maybe return Server struct instead of a future?
-pub async fn run_http_server() -> Result<(), std::io::Error> {
-    HttpServer::new(|| {
+pub async fn run_http_server<R: Resource + 'static>(
+    resource_config: ResourceConfig,
+    event_config: EventConfig,
+    encoder: Arc<EventEncoder>,
+) -> Result<(), std::io::Error> {
+    let state = web::Data::new(AdminState {
+        routing_key: resource_config
+            .routing_keys
+            .first()
+            .cloned()
+            .unwrap_or_default(),
+        event_config,
+        encoder,
+    });
+
+    HttpServer::new(move || {
         App::new()
+            .app_data(state.clone())
             .service(ping)
             .service(ready)
             .service(metrics_service)
+            .route(
+                "/resources/{fdkId}/publish",
+                web::post().to(publish_resource::<R>),
+            )
+            .route(
+                "/resources/{fdkId}/remove",
+                web::post().to(remove_resource::<R>),
+            )
     })
     .bind(("0.0.0.0", 8080))
     .unwrap_or_else(|e| {
@@ -1,60 +1,348 @@
-use std::{env, time::Duration};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
 
 use lazy_static::lazy_static;
-use rdkafka::{
-    producer::{FutureProducer, FutureRecord},
-    ClientConfig,
-};
-use schema_registry_converter::{
-    async_impl::{avro::AvroEncoder, schema_registry::SrSettings},
-    schema_registry_common::SubjectNameStrategy,
-};
+use rdkafka::{producer::FutureProducer, statistics::Statistics, ClientConfig, ClientContext};
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
 use serde::Serialize;
+use tracing::Instrument;
 
-use crate::EventConfig;
+use crate::{
+    encoder::EventEncoder,
+    metrics::{
+        ENCODE_TIME, KAFKA_BROKER_OUTBUF_MESSAGES, KAFKA_BROKER_RTT, KAFKA_BROKER_TX_ERRORS,
+        KAFKA_BROKER_TX_RETRIES, KAFKA_QUEUE_BYTES, KAFKA_QUEUE_MESSAGES, KAFKA_TOPIC_BATCH_SIZE,
+        LAST_PUBLISH_TIMESTAMP,
+    },
+    sink::{EventRecord, EventSink, SinkError},
+    telemetry::trace_headers,
+    EventConfig,
+};
 
 lazy_static! {
     pub static ref BROKERS: String = env::var("BROKERS").unwrap_or("localhost:9092".to_string());
     pub static ref SCHEMA_REGISTRY: String =
         env::var("SCHEMA_REGISTRY").unwrap_or("http://localhost:8081".to_string());
+    pub static ref STATISTICS_INTERVAL_MS: String =
+        env::var("KAFKA_STATISTICS_INTERVAL_MS").unwrap_or("15000".to_string());
+    pub static ref DRY_RUN: bool = env::var("DRY_RUN").is_ok_and(|value| value == "true");
+    pub static ref KEY_STRATEGY: KeyStrategy = env::var("KAFKA_KEY_STRATEGY")
+        .map(|strategy| KeyStrategy::from_name(&strategy))
+        .unwrap_or(Ok(KeyStrategy::FdkId))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "kafka key strategy error");
+            std::process::exit(1);
+        });
+    pub static ref PARTITIONER: Option<String> = env::var("KAFKA_PARTITIONER").ok();
+    pub static ref SUBJECT_STRATEGY: SubjectStrategy = env::var("SCHEMA_SUBJECT_STRATEGY")
+        .map(|strategy| SubjectStrategy::from_name(&strategy))
+        .unwrap_or(Ok(SubjectStrategy::RecordName))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "schema subject strategy error");
+            std::process::exit(1);
+        });
+    pub static ref EVENT_FORMAT: EventFormat = env::var("EVENT_FORMAT")
+        .map(|format| EventFormat::from_name(&format))
+        .unwrap_or(Ok(EventFormat::Avro))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "event format error");
+            std::process::exit(1);
+        });
+    pub static ref TOMBSTONE_MODE: TombstoneMode = env::var("REMOVAL_TOMBSTONES")
+        .map(|mode| TombstoneMode::from_name(&mode))
+        .unwrap_or(Ok(TombstoneMode::None))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "removal tombstones error");
+            std::process::exit(1);
+        });
+    pub static ref SNAPSHOT_TOPIC: Option<String> = env::var("SNAPSHOT_TOPIC").ok();
+    pub static ref QUEUE_MAX_MESSAGES: u32 = env::var("KAFKA_QUEUE_MAX_MESSAGES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(100000);
+    pub static ref QUEUE_TIMEOUT_MS: u64 = env::var("KAFKA_QUEUE_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30000);
 }
 
+pub type Producer = FutureProducer<StatisticsContext>;
+
 #[derive(Debug, thiserror::Error)]
 pub enum KafkaError {
     #[error(transparent)]
     SRCError(#[from] schema_registry_converter::error::SRCError),
     #[error(transparent)]
     RdkafkaError(#[from] rdkafka::error::KafkaError),
+    #[error(transparent)]
+    SinkError(#[from] SinkError),
+    #[error(transparent)]
+    SerdeError(#[from] serde_json::Error),
+    #[error(transparent)]
+    ProtobufError(#[from] protobuf::Error),
+    #[error("{0}")]
+    SchemaError(String),
+}
+
+/// The catalog and publisher of a resource, keying records by the matching key strategies.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OwnerIds {
+    pub catalog_id: Option<String>,
+    pub publisher_id: Option<String>,
 }
 
 pub trait Event: Serialize {
     fn key(&self) -> String;
+
+    /// The catalog and publisher of the resource, see `utils::owner_ids`.
+    fn owner_ids(&self) -> OwnerIds {
+        OwnerIds::default()
+    }
+
+    /// Whether the event removes the resource, tombstones are only produced for removals.
+    fn is_removal(&self) -> bool {
+        false
+    }
+}
+
+/// How the key of produced records is chosen, which decides the partition they are produced to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyStrategy {
+    /// Key by the event key, the resource's fdkId.
+    FdkId,
+    /// Key by the id of the catalog the resource is part of.
+    CatalogId,
+    /// Key by the id of the resource's publisher.
+    PublisherId,
+    /// Produce records without a key.
+    Null,
+}
+
+impl KeyStrategy {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "fdk_id" => Ok(Self::FdkId),
+            "catalog_id" => Ok(Self::CatalogId),
+            "publisher_id" => Ok(Self::PublisherId),
+            "null" => Ok(Self::Null),
+            _ => Err(format!("unknown kafka key strategy: '{}'", name)),
+        }
+    }
+
+    /// Whether keys are the catalog or publisher ids carried by events.
+    pub fn uses_owner_ids(&self) -> bool {
+        matches!(self, Self::CatalogId | Self::PublisherId)
+    }
+
+    /// Catalog and publisher ids fall back to the event key when the event has none.
+    fn key<E: Event>(&self, event: &E) -> Option<String> {
+        let owner_ids = event.owner_ids();
+        match self {
+            Self::FdkId => Some(event.key()),
+            Self::CatalogId => Some(owner_ids.catalog_id.unwrap_or_else(|| event.key())),
+            Self::PublisherId => Some(owner_ids.publisher_id.unwrap_or_else(|| event.key())),
+            Self::Null => None,
+        }
+    }
+}
+
+/// Schema registry subject naming strategy, used both when registering and encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubjectStrategy {
+    /// Subject is the record name, e.g. `no.fdk.dataset.DatasetEvent`.
+    RecordName,
+    /// Subject is the topic, e.g. `dataset-events-value`.
+    TopicName,
+    /// Subject is the topic and record name, e.g. `dataset-events-no.fdk.dataset.DatasetEvent`.
+    TopicRecordName,
+}
+
+impl SubjectStrategy {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "record_name" => Ok(Self::RecordName),
+            "topic_name" => Ok(Self::TopicName),
+            "topic_record_name" => Ok(Self::TopicRecordName),
+            _ => Err(format!("unknown schema subject strategy: '{}'", name)),
+        }
+    }
+}
+
+/// Serialization format of produced events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventFormat {
+    Avro,
+    Json,
+    Protobuf,
+}
+
+impl EventFormat {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "avro" => Ok(Self::Avro),
+            "json" => Ok(Self::Json),
+            "protobuf" => Ok(Self::Protobuf),
+            _ => Err(format!("unknown event format: '{}'", name)),
+        }
+    }
+}
+
+/// Whether removals produce tombstones, null-payload records keyed by fdkId, so the output topics
+/// can be compacted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TombstoneMode {
+    /// Only the remove event is produced.
+    None,
+    /// A tombstone is produced after the remove event.
+    Additional,
+    /// A tombstone is produced instead of the remove event.
+    Instead,
+}
+
+impl TombstoneMode {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "none" => Ok(Self::None),
+            "additional" => Ok(Self::Additional),
+            "instead" => Ok(Self::Instead),
+            _ => Err(format!("unknown removal tombstones mode: '{}'", name)),
+        }
+    }
+}
+
+/// Location of a record produced to Kafka, both are -1 for sinks without partitions.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct DeliveryReceipt {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Producer context exporting librdkafka statistics as Prometheus metrics.
+pub struct StatisticsContext;
+
+impl ClientContext for StatisticsContext {
+    fn stats(&self, statistics: Statistics) {
+        KAFKA_QUEUE_MESSAGES.set(statistics.msg_cnt as i64);
+        KAFKA_QUEUE_BYTES.set(statistics.msg_size as i64);
+
+        for broker in statistics.brokers.values() {
+            let name = broker.name.as_str();
+            KAFKA_BROKER_TX_ERRORS
+                .with_label_values(&[name])
+                .set(broker.txerrs as i64);
+            KAFKA_BROKER_TX_RETRIES
+                .with_label_values(&[name])
+                .set(broker.txretries as i64);
+            KAFKA_BROKER_OUTBUF_MESSAGES
+                .with_label_values(&[name])
+                .set(broker.outbuf_msg_cnt);
+
+            if let Some(rtt) = &broker.rtt {
+                // librdkafka reports round-trip times in microseconds
+                for (quantile, value) in [
+                    ("avg", rtt.avg),
+                    ("p50", rtt.p50),
+                    ("p95", rtt.p95),
+                    ("p99", rtt.p99),
+                ] {
+                    KAFKA_BROKER_RTT
+                        .with_label_values(&[name, quantile])
+                        .set(value as f64 / 1_000_000.0);
+                }
+            }
+        }
+
+        for topic in statistics.topics.values() {
+            for (quantile, value) in [
+                ("avg", topic.batchsize.avg),
+                ("p50", topic.batchsize.p50),
+                ("p99", topic.batchsize.p99),
+            ] {
+                KAFKA_TOPIC_BATCH_SIZE
+                    .with_label_values(&[topic.topic.as_str(), quantile])
+                    .set(value as f64);
+            }
+        }
+    }
 }
 
+/// Produces the event, and for removals a tombstone depending on the tombstone mode. The receipt
+/// is that of the last record produced to the event topic. The snapshot topic, when configured, is
+/// written after the event topic, so it never gets ahead of it.
 pub async fn send_event<E: Event>(
-    encoder: &mut AvroEncoder<'_>,
-    producer: &FutureProducer,
+    encoder: &EventEncoder,
+    sink: &dyn EventSink,
     event_config: &EventConfig,
     event: E,
-) -> Result<(), KafkaError> {
-    let key = event.key();
+) -> Result<DeliveryReceipt, KafkaError> {
+    let value = serde_json::to_value(&event)?;
+    let id = event.key();
+    let key = KEY_STRATEGY.key(&event);
+    let removal = event.is_removal();
+
+    let payload = match (removal, event_config.tombstones) {
+        (true, TombstoneMode::Instead) => None,
+        _ => {
+            let encode_start_time = Instant::now();
+            let encoded =
+                tracing::info_span!("encode_event").in_scope(|| encoder.encode(event, &value))?;
+            ENCODE_TIME.observe(encode_start_time.elapsed().as_secs_f64());
+            Some(encoded)
+        }
+    };
 
-    let encoded = encoder
-        .encode_struct(
-            event,
-            &SubjectNameStrategy::RecordNameStrategy(event_config.name.to_string()),
-        )
-        .await?;
+    let topic = &event_config.topic;
+    let receipt = match &payload {
+        Some(payload) => {
+            send_record(sink, topic, key, value.clone(), Some(payload.clone())).await?
+        }
+        None => send_tombstone(sink, topic, id.clone()).await?,
+    };
+    let receipt = match (removal, event_config.tombstones) {
+        (true, TombstoneMode::Additional) => send_tombstone(sink, topic, id.clone()).await?,
+        _ => receipt,
+    };
 
-    let record = FutureRecord::to(&event_config.topic)
-        .key(&key)
-        .payload(&encoded);
-    producer
-        .send(record, Duration::from_secs(0))
-        .await
-        .map_err(|e| e.0)?;
+    if let Some(snapshot_topic) = &event_config.snapshot_topic {
+        match (removal, payload) {
+            (false, Some(payload)) => {
+                send_record(sink, snapshot_topic, Some(id), value, Some(payload)).await?
+            }
+            _ => send_tombstone(sink, snapshot_topic, id).await?,
+        };
+    }
+    LAST_PUBLISH_TIMESTAMP.set(chrono::Utc::now().timestamp_millis() as f64 / 1000.0);
+
+    Ok(receipt)
+}
+
+async fn send_record(
+    sink: &dyn EventSink,
+    topic: &str,
+    key: Option<String>,
+    event: serde_json::Value,
+    payload: Option<Vec<u8>>,
+) -> Result<DeliveryReceipt, KafkaError> {
+    let span = tracing::info_span!("send_event", topic);
+    let record = EventRecord {
+        topic: topic.to_string(),
+        key,
+        event,
+        payload,
+        headers: span.in_scope(trace_headers),
+    };
+    Ok(sink.send(record).instrument(span).await?)
+}
 
-    Ok(())
+/// Tombstones are keyed by fdkId whatever the key strategy, as compaction is per resource.
+async fn send_tombstone(
+    sink: &dyn EventSink,
+    topic: &str,
+    id: String,
+) -> Result<DeliveryReceipt, KafkaError> {
+    send_record(sink, topic, Some(id), serde_json::Value::Null, None).await
 }
 
 pub fn create_sr_settings() -> Result<SrSettings, KafkaError> {
@@ -72,11 +360,230 @@ pub fn create_sr_settings() -> Result<SrSettings, KafkaError> {
     Ok(sr_settings)
 }
 
-pub fn create_producer() -> Result<FutureProducer, KafkaError> {
-    let producer = ClientConfig::new()
+pub fn create_producer() -> Result<Producer, KafkaError> {
+    let mut config = ClientConfig::new();
+    config
         .set("bootstrap.servers", BROKERS.clone())
         .set("message.timeout.ms", "5000")
         .set("compression.type", "snappy")
-        .create()?;
+        .set("statistics.interval.ms", STATISTICS_INTERVAL_MS.clone())
+        .set(
+            "queue.buffering.max.messages",
+            QUEUE_MAX_MESSAGES.to_string(),
+        );
+    if let Some(partitioner) = PARTITIONER.as_ref() {
+        config.set("partitioner", partitioner);
+    }
+
+    let producer = config.create_with_context(StatisticsContext)?;
     Ok(producer)
 }
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::statistics::{Broker, Topic, Window};
+
+    use crate::{
+        source::Acknowledgement,
+        testing::{report, stub_get, test_event_config, TestHarness, TestResource},
+    };
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TestEvent {
+        #[serde(rename = "fdkId")]
+        fdk_id: String,
+        #[serde(skip)]
+        owner_ids: OwnerIds,
+    }
+
+    impl Event for TestEvent {
+        fn key(&self) -> String {
+            self.fdk_id.clone()
+        }
+
+        fn owner_ids(&self) -> OwnerIds {
+            self.owner_ids.clone()
+        }
+    }
+
+    fn key(strategy: KeyStrategy, owner_ids: OwnerIds) -> Option<String> {
+        let event = TestEvent {
+            fdk_id: "123".to_string(),
+            owner_ids,
+        };
+        strategy.key(&event)
+    }
+
+    #[test]
+    fn keys_by_strategy() {
+        let owner_ids = OwnerIds {
+            catalog_id: Some("456".to_string()),
+            publisher_id: Some("789".to_string()),
+        };
+        for (strategy, expected) in [
+            (KeyStrategy::FdkId, Some("123")),
+            (KeyStrategy::CatalogId, Some("456")),
+            (KeyStrategy::PublisherId, Some("789")),
+            (KeyStrategy::Null, None),
+        ] {
+            assert_eq!(key(strategy, owner_ids.clone()).as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_event_key_without_owner_ids() {
+        assert_eq!(
+            key(KeyStrategy::CatalogId, OwnerIds::default()).as_deref(),
+            Some("123")
+        );
+        assert_eq!(
+            key(KeyStrategy::PublisherId, OwnerIds::default()).as_deref(),
+            Some("123")
+        );
+    }
+
+    #[test]
+    fn statistics_are_exported_as_metrics() {
+        let broker = Broker {
+            name: "kafka:9092/1".to_string(),
+            txerrs: 2,
+            txretries: 3,
+            outbuf_msg_cnt: 4,
+            rtt: Some(Window {
+                avg: 1500,
+                p50: 1000,
+                p95: 2000,
+                p99: 3000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let topic = Topic {
+            topic: "test-events".to_string(),
+            batchsize: Window {
+                avg: 512,
+                p50: 256,
+                p99: 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let statistics = Statistics {
+            msg_cnt: 12,
+            msg_size: 3400,
+            brokers: [(broker.name.clone(), broker)].into(),
+            topics: [(topic.topic.clone(), topic)].into(),
+            ..Default::default()
+        };
+
+        crate::metrics::register_metrics();
+        StatisticsContext.stats(statistics);
+        let metrics = crate::metrics::get_metrics().unwrap();
+
+        for line in [
+            "kafka_producer_queue_messages 12",
+            "kafka_producer_queue_bytes 3400",
+            r#"kafka_broker_tx_errors{broker="kafka:9092/1"} 2"#,
+            r#"kafka_broker_tx_retries{broker="kafka:9092/1"} 3"#,
+            r#"kafka_broker_outbuf_messages{broker="kafka:9092/1"} 4"#,
+            r#"kafka_broker_rtt{broker="kafka:9092/1",quantile="avg"} 0.0015"#,
+            r#"kafka_topic_batch_size{quantile="p99",topic="test-events"} 1024"#,
+        ] {
+            assert!(
+                metrics.contains(line),
+                "missing '{}' in:\n{}",
+                line,
+                metrics
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn removals_produce_tombstones() {
+        let harness = TestHarness::new(EventConfig {
+            tombstones: TombstoneMode::Additional,
+            ..test_event_config("additional-tombstone-events")
+        });
+        harness
+            .deliver::<TestResource>("tests.harvested", &report(&[], &["tombstone-1"]))
+            .await;
+
+        let records = harness.records().await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].value["type"], "TEST_REMOVED");
+        assert_eq!(records[1].key.as_deref(), Some("tombstone-1"));
+        assert!(harness.raw_records()[1].payload.is_none());
+
+        let harness = TestHarness::new(EventConfig {
+            tombstones: TombstoneMode::Instead,
+            ..test_event_config("tombstone-events")
+        });
+        harness
+            .deliver::<TestResource>("tests.harvested", &report(&[], &["tombstone-2"]))
+            .await;
+
+        let records = harness.raw_records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key.as_deref(), Some("tombstone-2"));
+        assert!(records[0].payload.is_none());
+    }
+
+    #[tokio::test]
+    async fn events_are_mirrored_to_snapshot_topic() {
+        let harness = TestHarness::new(EventConfig {
+            snapshot_topic: Some("snapshots".to_string()),
+            ..test_event_config("snapshot-events")
+        });
+        stub_get("/test-resources/snapshot-1", 200, "<graph>");
+
+        harness
+            .deliver::<TestResource>("tests.harvested", &report(&["snapshot-1"], &["snapshot-2"]))
+            .await;
+
+        let records = harness.records().await.unwrap();
+        let topics = records
+            .iter()
+            .map(|record| (record.topic.as_str(), record.key.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            topics,
+            [
+                ("snapshot-events", "snapshot-1"),
+                ("snapshots", "snapshot-1"),
+                ("snapshot-events", "snapshot-2"),
+                ("snapshots", "snapshot-2"),
+            ]
+        );
+        assert_eq!(records[1].value["type"], "TEST_HARVESTED");
+        assert_eq!(records[1].value["graph"], "<graph>");
+        assert_eq!(records[2].value["type"], "TEST_REMOVED");
+        assert!(harness.raw_records()[3].payload.is_none());
+    }
+
+    #[tokio::test]
+    async fn events_are_published_as_json_and_protobuf() {
+        stub_get("/test-resources/formats-1", 200, "<graph>");
+
+        for format in [EventFormat::Json, EventFormat::Protobuf] {
+            let harness = TestHarness::new(EventConfig {
+                format,
+                ..test_event_config(&format!("{:?}-events", format))
+            });
+
+            let ack = harness
+                .deliver::<TestResource>("tests.harvested", &report(&["formats-1"], &["formats-2"]))
+                .await;
+            assert_eq!(ack, Acknowledgement::Ack);
+
+            let records = harness.records().await.unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].value["type"], "TEST_HARVESTED");
+            assert_eq!(records[0].value["fdkId"], "formats-1");
+            assert_eq!(records[0].value["graph"], "<graph>");
+            assert_eq!(records[0].value["timestamp"], 1644575406110i64);
+            assert_eq!(records[1].value["type"], "TEST_REMOVED");
+        }
+    }
+}
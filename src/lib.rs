@@ -1,38 +1,74 @@
-use std::time::Instant;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use chrono::DateTime;
 use error::Error;
 use kafka::create_sr_settings;
-use lapin::{
-    message::{Delivery, DeliveryResult},
-    options::BasicAckOptions,
-};
 use lazy_static::lazy_static;
 use rabbit::HarvestReport;
-use rdkafka::producer::FutureProducer;
-use schema_registry_converter::async_impl::{avro::AvroEncoder, schema_registry::SrSettings};
+use schema_registry_converter::{
+    async_impl::schema_registry::SrSettings, schema_registry_common::SubjectNameStrategy,
+};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
+    backfill::{run_backfill, BackfillArgs},
+    encoder::EventEncoder,
+    harvester::{
+        HarvesterError, NotFoundPolicy, HARVESTER_MAX_CONCURRENT_REQUESTS, HARVESTER_RATE_LIMIT,
+        NOT_FOUND_POLICY, NOT_FOUND_RETRIES, NOT_FOUND_RETRY_DELAY_MS,
+    },
     http::run_http_server,
-    kafka::{send_event, BROKERS, SCHEMA_REGISTRY},
-    metrics::{register_metrics, PROCESSED_MESSAGES, PROCESSING_TIME},
-    schema::setup_schema,
+    kafka::{
+        send_event, DeliveryReceipt, EventFormat, KeyStrategy, Producer, SubjectStrategy,
+        TombstoneMode, BROKERS, DRY_RUN, KEY_STRATEGY, PARTITIONER, SCHEMA_REGISTRY,
+    },
+    metrics::{
+        register_metrics, HARVESTER_NOT_FOUND, IN_FLIGHT_DELIVERIES, PROCESSED_EVENTS,
+        PROCESSED_MESSAGES, PROCESSING_TIME,
+    },
+    ordering::STALE_EVENT_POLICY,
+    schema::resolve_schema_id,
+    sink::{create_sink, EventSink, EVENT_SINK},
+    source::{
+        create_source, Acknowledgement, MessageHandler, ReportMessage, MAX_REDELIVERIES,
+        REPORT_SOURCE,
+    },
+    telemetry::parent_context,
 };
 
+mod backfill;
+mod circuit;
+mod encoder;
 pub mod error;
+pub mod harvester;
 mod http;
 pub mod kafka;
 mod metrics;
+mod ordering;
 mod rabbit;
 mod schema;
+pub mod sink;
+pub mod source;
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod utils;
 
 lazy_static! {
-    pub static ref PRODUCER: FutureProducer = kafka::create_producer().unwrap_or_else(|e| {
+    pub static ref PRODUCER: Producer = kafka::create_producer().unwrap_or_else(|e| {
         tracing::error!(error = e.to_string(), "kafka producer creation error");
         std::process::exit(1);
     });
+    pub static ref SINK: Box<dyn EventSink> = create_sink().unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "event sink creation error");
+        std::process::exit(1);
+    });
     pub static ref SR_SETTINGS: SrSettings = create_sr_settings().unwrap_or_else(|e| {
         tracing::error!(error = e.to_string(), "sr settings creation error");
         std::process::exit(1);
@@ -49,6 +85,53 @@ pub struct EventConfig {
     pub name: String,
     pub topic: String,
     pub schema: String,
+    pub subject_strategy: SubjectStrategy,
+    pub format: EventFormat,
+    pub tombstones: TombstoneMode,
+    /// Compacted topic mirroring the latest event per resource.
+    pub snapshot_topic: Option<String>,
+}
+
+impl EventConfig {
+    /// The subject the schema is registered under.
+    pub fn subject(&self) -> String {
+        self.topic_subject(&self.topic)
+    }
+
+    /// All subjects the schema is registered under, with topic based strategies including the
+    /// snapshot topic's.
+    pub fn subjects(&self) -> Vec<String> {
+        let mut subjects = vec![self.subject()];
+        if let Some(snapshot_topic) = &self.snapshot_topic {
+            let subject = self.topic_subject(snapshot_topic);
+            if !subjects.contains(&subject) {
+                subjects.push(subject);
+            }
+        }
+        subjects
+    }
+
+    fn topic_subject(&self, topic: &str) -> String {
+        match self.subject_strategy {
+            SubjectStrategy::RecordName => self.name.clone(),
+            SubjectStrategy::TopicName => format!("{}-value", topic),
+            SubjectStrategy::TopicRecordName => format!("{}-{}", topic, self.name),
+        }
+    }
+
+    fn subject_name_strategy(&self) -> SubjectNameStrategy {
+        match self.subject_strategy {
+            SubjectStrategy::RecordName => {
+                SubjectNameStrategy::RecordNameStrategy(self.name.clone())
+            }
+            SubjectStrategy::TopicName => {
+                SubjectNameStrategy::TopicNameStrategy(self.topic.clone(), false)
+            }
+            SubjectStrategy::TopicRecordName => {
+                SubjectNameStrategy::TopicRecordNameStrategy(self.topic.clone(), self.name.clone())
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -61,14 +144,50 @@ pub trait Resource {
         timestamp: i64,
         change: ChangeType,
     ) -> Result<Option<Self::Event>, Error>;
+
+    /// Lists the ids of all resources in the harvester, used when backfilling.
+    async fn resource_ids() -> Result<Vec<String>, Error> {
+        Err("listing resources is not supported".into())
+    }
+
+    /// Lists the ids of the resources in a removed catalog or data source.
+    async fn removed_ids(scope: RemovalScope, id: &str) -> Result<Vec<String>, Error> {
+        Err(format!("listing resources by {:?} '{}' is not supported", scope, id).into())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ChangeType {
     CreateOrUpdate,
     Remove,
 }
 
+/// What a removal report covers beyond individual resources.
+#[derive(Clone, Copy, Debug)]
+pub enum RemovalScope {
+    Catalog,
+    DataSource,
+}
+
+impl RemovalScope {
+    /// Query parameter filtering the harvester's id listing.
+    pub fn query_param(&self) -> &'static str {
+        match self {
+            RemovalScope::Catalog => "catalogId",
+            RemovalScope::DataSource => "dataSourceId",
+        }
+    }
+}
+
+impl ChangeType {
+    fn metric_label(&self) -> &'static str {
+        match self {
+            ChangeType::CreateOrUpdate => "create_or_update",
+            ChangeType::Remove => "remove",
+        }
+    }
+}
+
 pub async fn run_event_publisher<R: Resource + 'static>(
     resource_config: ResourceConfig,
     event_config: EventConfig,
@@ -78,67 +197,158 @@ pub async fn run_event_publisher<R: Resource + 'static>(
         schema_registry = SCHEMA_REGISTRY.to_string(),
         consumer_name = resource_config.consumer_name,
         output_topic = event_config.topic,
+        schema_subject = event_config.subject(),
         routing_keys = format!("{:?}", resource_config.routing_keys),
+        not_found_policy = format!("{:?}", *NOT_FOUND_POLICY),
+        stale_event_policy = format!("{:?}", *STALE_EVENT_POLICY),
+        harvester_rate_limit = *HARVESTER_RATE_LIMIT,
+        harvester_max_concurrent_requests = *HARVESTER_MAX_CONCURRENT_REQUESTS,
+        report_source = REPORT_SOURCE.as_str(),
+        event_sink = EVENT_SINK.as_str(),
+        dry_run = *DRY_RUN,
+        key_strategy = format!("{:?}", *KEY_STRATEGY),
+        partitioner = PARTITIONER.as_deref(),
+        tombstones = format!("{:?}", event_config.tombstones),
+        snapshot_topic = event_config.snapshot_topic.as_deref(),
         "starting service"
     );
+    if event_config.tombstones != TombstoneMode::None && *KEY_STRATEGY != KeyStrategy::FdkId {
+        tracing::warn!("tombstones are keyed by fdkId, compaction expects the fdk_id key strategy");
+    }
+
+    let backfill_args = BackfillArgs::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "invalid arguments");
+        std::process::exit(1);
+    });
 
     register_metrics();
 
-    setup_schema(&SR_SETTINGS, &event_config)
+    // A dry run must not register schemas, it encodes with the id of the registered schema if any
+    let schema_id = resolve_schema_id(&SR_SETTINGS, &event_config, *DRY_RUN)
         .await
-        .unwrap_or_else(|e| {
-            tracing::error!(error = e.to_string(), "schema registration error");
-            std::process::exit(1);
+        .unwrap_or_else(|e| match *DRY_RUN {
+            true => {
+                tracing::warn!(
+                    error = e.to_string(),
+                    "schema not registered, dry run encodes with schema id 0"
+                );
+                0
+            }
+            false => {
+                tracing::error!(error = e.to_string(), "schema registration error");
+                std::process::exit(1);
+            }
         });
-
-    let channel = rabbit::connect().await.unwrap_or_else(|e| {
-        tracing::error!(error = e.to_string(), "rabbit connection error");
-        std::process::exit(1);
-    });
-    rabbit::setup(
-        &channel,
-        &resource_config.consumer_name,
-        &resource_config.routing_keys,
-    )
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!(error = e.to_string(), "rabbit setup error");
+    let encoder = EventEncoder::new(&event_config, schema_id).unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "event encoder creation error");
         std::process::exit(1);
     });
-    let consumer = rabbit::create_consumer(&channel, &resource_config.consumer_name)
+    let encoder = Arc::new(encoder);
+
+    if let Some(args) = backfill_args {
+        run_backfill::<R>(
+            SINK.as_ref(),
+            &encoder,
+            &resource_config,
+            &event_config,
+            args,
+        )
         .await
         .unwrap_or_else(|e| {
-            tracing::error!(error = e.to_string(), "rabbit consumer creation error");
+            tracing::error!(error = e.to_string(), "backfill error");
             std::process::exit(1);
         });
+        return;
+    }
 
-    consumer.set_delegate(move |delivery| receive_message::<R>(event_config.clone(), delivery));
-
-    run_http_server().await.unwrap_or_else(|e| {
-        tracing::error!(error = e.to_string(), "failed to run http server");
+    let source = create_source(&resource_config).await.unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "report source creation error");
         std::process::exit(1);
     });
-}
+    let handler: MessageHandler = {
+        let encoder = encoder.clone();
+        let event_config = event_config.clone();
+        Arc::new(move |message| {
+            let encoder = encoder.clone();
+            let event_config = event_config.clone();
+            Box::pin(async move {
+                receive_message::<R>(SINK.as_ref(), &encoder, &event_config, message).await
+            })
+        })
+    };
 
-async fn receive_message<R: Resource>(event_config: EventConfig, delivery: DeliveryResult) {
-    let delivery = match delivery {
-        Ok(Some(delivery)) => delivery,
-        Ok(None) => return,
-        Err(error) => {
-            tracing::error!(error = error.to_string(), "failed to consume message");
+    tokio::select! {
+        result = source.run(handler) => {
+            if let Err(e) = result {
+                tracing::error!(error = e.to_string(), "failed to consume messages");
+            }
             std::process::exit(1);
         }
-    };
+        result = run_http_server::<R>(resource_config, event_config, encoder) => {
+            result.unwrap_or_else(|e| {
+                tracing::error!(error = e.to_string(), "failed to run http server");
+                std::process::exit(1);
+            });
+        }
+    }
+}
+
+async fn receive_message<R: Resource>(
+    sink: &dyn EventSink,
+    encoder: &EventEncoder,
+    event_config: &EventConfig,
+    message: ReportMessage,
+) -> Acknowledgement {
+    let span = tracing::info_span!(
+        "receive_message",
+        routing_key = message.routing_key.as_str()
+    );
+    if let Err(e) = span.set_parent(parent_context(&message.headers)) {
+        tracing::warn!(error = e.to_string(), "unable to set parent trace context");
+    }
 
+    // Pause until a probe is due, or the probe in flight closes the circuit, rather than fail every
+    // resource
+    if let Some(delay) = circuit::probe_delay() {
+        tracing::warn!(
+            routing_key = message.routing_key.as_str(),
+            delay_millis = delay.as_millis(),
+            "circuit open, waiting before handling message"
+        );
+        circuit::wait_for_probes().await;
+    }
+
+    IN_FLIGHT_DELIVERIES.inc();
     let start_time = Instant::now();
-    let result =
-        handle_message::<R>(&PRODUCER, SR_SETTINGS.clone(), &event_config, &delivery).await;
+    let result = handle_message::<R>(sink, encoder, event_config, &message)
+        .instrument(span)
+        .await;
     let elapsed_millis = start_time.elapsed().as_millis();
+    IN_FLIGHT_DELIVERIES.dec();
 
-    let metric_status_label = match result {
+    let (metric_status_label, acknowledgement) = match result {
         Ok(_) => {
             tracing::info!(elapsed_millis, "message handled successfully");
-            "success"
+            ("success", Acknowledgement::Ack)
+        }
+        Err(e) if e.is_transient() && message.redeliveries >= *MAX_REDELIVERIES => {
+            // Most likely the message itself keeps failing, or opening the circuit
+            tracing::error!(
+                elapsed_millis,
+                redeliveries = message.redeliveries,
+                error = e.to_string(),
+                "message failed, giving up after too many redeliveries"
+            );
+            ("error", Acknowledgement::Ack)
+        }
+        Err(e) if e.is_transient() => {
+            tracing::warn!(
+                elapsed_millis,
+                error = e.to_string(),
+                "message failed, requeueing"
+            );
+            circuit::wait_for_probes().await;
+            ("requeued", Acknowledgement::Requeue)
         }
         Err(e) => {
             tracing::error!(
@@ -146,7 +356,7 @@ async fn receive_message<R: Resource>(event_config: EventConfig, delivery: Deliv
                 error = e.to_string(),
                 "failed while handling message"
             );
-            "error"
+            ("error", Acknowledgement::Ack)
         }
     };
     PROCESSED_MESSAGES
@@ -154,19 +364,16 @@ async fn receive_message<R: Resource>(event_config: EventConfig, delivery: Deliv
         .inc();
     PROCESSING_TIME.observe(elapsed_millis as f64 / 1000.0);
 
-    delivery
-        .ack(BasicAckOptions::default())
-        .await
-        .unwrap_or_else(|e| tracing::error!(error = e.to_string(), "failed to ack message"));
+    acknowledgement
 }
 
 async fn handle_message<R: Resource>(
-    producer: &FutureProducer,
-    sr_settings: SrSettings,
+    sink: &dyn EventSink,
+    encoder: &EventEncoder,
     event_config: &EventConfig,
-    delivery: &Delivery,
+    message: &ReportMessage,
 ) -> Result<(), Error> {
-    let reports: Vec<HarvestReport> = serde_json::from_slice(&delivery.data)?;
+    let reports: Vec<HarvestReport> = serde_json::from_slice(&message.data)?;
 
     let changed_resource_count = reports
         .iter()
@@ -181,83 +388,232 @@ async fn handle_message<R: Resource>(
                 .map_or(0, |resources| resources.len())
         })
         .sum::<usize>();
+    let removed_catalog_count = reports
+        .iter()
+        .map(|element| {
+            element
+                .removed_catalogs
+                .as_ref()
+                .map_or(0, |resources| resources.len())
+        })
+        .sum::<usize>();
+    let removed_data_source_count = reports
+        .iter()
+        .map(|element| {
+            element
+                .removed_data_sources
+                .as_ref()
+                .map_or(0, |resources| resources.len())
+        })
+        .sum::<usize>();
 
     tracing::debug!(
-        routing_key = delivery.routing_key.as_str(),
+        routing_key = message.routing_key.as_str(),
         reports = format!("{:?}", reports),
         "processing event"
     );
 
     tracing::info!(
-        routing_key = delivery.routing_key.as_str(),
+        routing_key = message.routing_key.as_str(),
         reports = reports.len(),
         changed_resource_count,
         removed_resource_count,
+        removed_catalog_count,
+        removed_data_source_count,
         "processing event"
     );
-    let mut encoder = AvroEncoder::new(sr_settings);
 
+    let mut changes = Vec::new();
     for element in reports {
-        let timestamp = DateTime::parse_from_str(&element.start_time, "%Y-%m-%d %H:%M:%S%.f %z")?
-            .timestamp_millis();
-
-        for resource in element.changed_resources {
-            if let Err(e) = handle_event::<R>(
-                &mut encoder,
-                &producer,
-                &event_config,
-                delivery.routing_key.as_str(),
-                resource.fdk_id.clone(),
-                timestamp,
-                ChangeType::CreateOrUpdate,
+        let span = tracing::info_span!("report_changes", start_time = element.start_time.as_str());
+        changes.extend(report_changes::<R>(element).instrument(span).await?);
+    }
+
+    let routing_key = message.routing_key.as_str();
+    let resource_type = routing_key.split('.').next().unwrap_or_default();
+    let mut changes = collapse_changes(changes, resource_type);
+    let mut attempt = 0;
+    loop {
+        let retry_not_found =
+            *NOT_FOUND_POLICY == NotFoundPolicy::Retry && attempt < *NOT_FOUND_RETRIES;
+        let mut not_found = Vec::new();
+        for change in changes {
+            let span = tracing::info_span!(
+                "handle_event",
+                id = change.id.as_str(),
+                change = format!("{:?}", change.change)
+            );
+            let outcome = match handle_event::<R>(
+                encoder,
+                sink,
+                event_config,
+                routing_key,
+                change.clone(),
+                retry_not_found,
             )
+            .instrument(span)
             .await
             {
-                tracing::error!(
-                    id = resource.fdk_id,
-                    change = format!("{:?}", ChangeType::CreateOrUpdate),
-                    error = e.to_string(),
-                    "failed while handling event"
-                );
-            }
-        }
-
-        if let Some(removed_resources) = element.removed_resources {
-            for resource in removed_resources {
-                if let Err(e) = handle_event::<R>(
-                    &mut encoder,
-                    &producer,
-                    &event_config,
-                    delivery.routing_key.as_str(),
-                    resource.fdk_id.clone(),
-                    timestamp,
-                    ChangeType::Remove,
-                )
-                .await
-                {
+                Ok(Some(_)) => "published",
+                Ok(None) => "skipped",
+                Err(Error::HarvesterError(HarvesterError::NotFound(_))) if retry_not_found => {
+                    not_found.push(change);
+                    continue;
+                }
+                // The remaining resources would fail too, have the message redelivered instead
+                Err(e) if e.is_circuit_open() => return Err(e),
+                Err(e) => {
                     tracing::error!(
-                        id = resource.fdk_id,
-                        change = format!("{:?}", ChangeType::Remove),
+                        id = change.id,
+                        change = format!("{:?}", change.change),
                         error = e.to_string(),
                         "failed while handling event"
                     );
+                    "error"
                 }
+            };
+            PROCESSED_EVENTS
+                .with_label_values(&[resource_type, change.change.metric_label(), outcome])
+                .inc();
+        }
+
+        if not_found.is_empty() {
+            return Ok(());
+        }
+        // Retried once the rest of the delivery is published, waiting once for all of them
+        attempt += 1;
+        tracing::warn!(
+            resources = not_found.len(),
+            attempt,
+            "resources not found, retrying"
+        );
+        tokio::time::sleep(Duration::from_millis(*NOT_FOUND_RETRY_DELAY_MS)).await;
+        changes = not_found;
+    }
+}
+
+/// A change to a resource, timestamped with the start time of its harvest report.
+#[derive(Clone, Debug)]
+struct ResourceChange {
+    id: String,
+    change: ChangeType,
+    timestamp: i64,
+}
+
+/// Lists the changes in a report, expanding removed catalogs and data sources to their resources.
+async fn report_changes<R: Resource>(report: HarvestReport) -> Result<Vec<ResourceChange>, Error> {
+    let timestamp =
+        DateTime::parse_from_str(&report.start_time, "%Y-%m-%d %H:%M:%S%.f %z")?.timestamp_millis();
+
+    let removed_scopes = report
+        .removed_catalogs
+        .unwrap_or_default()
+        .into_iter()
+        .map(|catalog| (RemovalScope::Catalog, catalog.fdk_id))
+        .chain(
+            report
+                .removed_data_sources
+                .unwrap_or_default()
+                .into_iter()
+                .map(|data_source| (RemovalScope::DataSource, data_source.id)),
+        );
+    let mut removed_in_scopes = Vec::new();
+    for (scope, id) in removed_scopes {
+        match R::removed_ids(scope, &id).await {
+            Ok(ids) => {
+                tracing::info!(
+                    scope = format!("{:?}", scope),
+                    id,
+                    resources = ids.len(),
+                    "expanding removal"
+                );
+                removed_in_scopes.extend(ids);
             }
+            // The removal would be lost, have the message redelivered instead
+            Err(e) if e.is_transient() => return Err(e),
+            Err(e) => tracing::error!(
+                scope = format!("{:?}", scope),
+                id,
+                error = e.to_string(),
+                "failed to list resources of removal"
+            ),
         }
     }
 
-    Ok(())
+    Ok(report
+        .changed_resources
+        .into_iter()
+        .map(|resource| (resource.fdk_id, ChangeType::CreateOrUpdate))
+        .chain(
+            report
+                .removed_resources
+                .unwrap_or_default()
+                .into_iter()
+                .map(|resource| resource.fdk_id)
+                .chain(removed_in_scopes)
+                .map(|id| (id, ChangeType::Remove)),
+        )
+        .map(|(id, change)| ResourceChange {
+            id,
+            change,
+            timestamp,
+        })
+        .collect())
+}
+
+/// Collapses the changes of each resource in a delivery to the one from the latest report, keeping
+/// the order resources first appear in. Of changes from reports with the same start time the last
+/// wins, so a resource both changed and removed in one report is removed.
+fn collapse_changes(changes: Vec<ResourceChange>, resource_type: &str) -> Vec<ResourceChange> {
+    let mut collapsed: Vec<ResourceChange> = Vec::new();
+    let mut positions = HashMap::new();
+    for change in changes {
+        let position = match positions.get(&change.id) {
+            Some(position) => *position,
+            None => {
+                positions.insert(change.id.clone(), collapsed.len());
+                collapsed.push(change);
+                continue;
+            }
+        };
+
+        let current = &mut collapsed[position];
+        let dropped = match change.timestamp >= current.timestamp {
+            true => std::mem::replace(current, change),
+            false => change,
+        };
+        if dropped.change != current.change {
+            tracing::warn!(
+                id = current.id,
+                kept = format!("{:?}", current.change),
+                kept_timestamp = current.timestamp,
+                dropped = format!("{:?}", dropped.change),
+                dropped_timestamp = dropped.timestamp,
+                "conflicting changes for resource in delivery"
+            );
+        }
+        PROCESSED_EVENTS
+            .with_label_values(&[resource_type, dropped.change.metric_label(), "deduplicated"])
+            .inc();
+    }
+    collapsed
 }
 
+/// Publishes the event of a changed resource. With `retry_not_found` and the retry policy, a
+/// resource the harvester does not have fails with `NotFound`, for the caller to retry later.
 async fn handle_event<R: Resource>(
-    mut encoder: &mut AvroEncoder<'_>,
-    producer: &FutureProducer,
+    encoder: &EventEncoder,
+    sink: &dyn EventSink,
     event_config: &EventConfig,
     routing_key: &str,
-    id: String,
-    timestamp: i64,
-    change: ChangeType,
-) -> Result<(), Error> {
+    resource_change: ResourceChange,
+    retry_not_found: bool,
+) -> Result<Option<DeliveryReceipt>, Error> {
+    let ResourceChange {
+        id,
+        change,
+        timestamp,
+    } = resource_change;
     tracing::debug!(
         routing_key,
         id = id.as_str(),
@@ -265,8 +621,358 @@ async fn handle_event<R: Resource>(
         "processing event"
     );
 
-    if let Some(event) = R::event(routing_key, id, timestamp, change).await? {
-        send_event(&mut encoder, &producer, &event_config, event).await?;
+    // Reserved before fetching, so stale events do not cost a harvester request, and an older event
+    // handled by a concurrent delivery is dropped. Rolled back unless the event is published.
+    let Some(reservation) = STALE_EVENT_POLICY.try_reserve(&id, timestamp) else {
+        return Ok(None);
     };
-    Ok(())
+
+    match resource_event::<R>(routing_key, id.clone(), timestamp, change, retry_not_found).await? {
+        Some(event) => {
+            let receipt = publish_event(encoder, sink, event_config, id, timestamp, event).await?;
+            reservation.commit();
+            Ok(Some(receipt))
+        }
+        None => Ok(None),
+    }
+}
+
+async fn publish_event<E: kafka::Event>(
+    encoder: &EventEncoder,
+    sink: &dyn EventSink,
+    event_config: &EventConfig,
+    id: String,
+    timestamp: i64,
+    event: E,
+) -> Result<DeliveryReceipt, Error> {
+    let receipt = send_event(encoder, sink, event_config, event).await?;
+    STALE_EVENT_POLICY.published(&id, timestamp);
+    tracing::info!(
+        id,
+        topic = event_config.topic,
+        partition = receipt.partition,
+        offset = receipt.offset,
+        "event published"
+    );
+    Ok(receipt)
+}
+
+/// Creates the event for a resource, applying the configured policy when a changed resource is
+/// no longer found in the harvester.
+async fn resource_event<R: Resource>(
+    routing_key: &str,
+    id: String,
+    timestamp: i64,
+    change: ChangeType,
+    retry_not_found: bool,
+) -> Result<Option<R::Event>, Error> {
+    match R::event(routing_key, id.clone(), timestamp, change).await {
+        Err(Error::HarvesterError(HarvesterError::NotFound(url))) => match *NOT_FOUND_POLICY {
+            NotFoundPolicy::Remove => {
+                tracing::warn!(id, url, "resource not found, publishing removal");
+                HARVESTER_NOT_FOUND.with_label_values(&["removed"]).inc();
+                R::event(routing_key, id, timestamp, ChangeType::Remove).await
+            }
+            NotFoundPolicy::Retry if retry_not_found => {
+                tracing::warn!(id, url, "resource not found, retrying later");
+                HARVESTER_NOT_FOUND.with_label_values(&["retried"]).inc();
+                Err(HarvesterError::NotFound(url).into())
+            }
+            NotFoundPolicy::Skip | NotFoundPolicy::Retry => {
+                tracing::warn!(id, url, "resource not found, skipping");
+                HARVESTER_NOT_FOUND.with_label_values(&["skipped"]).inc();
+                Ok(None)
+            }
+        },
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use crate::testing::{stub_get, stub_slow_get, test_event_config, TestHarness, TestResource};
+
+    use super::*;
+
+    fn event_config(subject_strategy: SubjectStrategy) -> EventConfig {
+        EventConfig {
+            name: "no.fdk.dataset.DatasetEvent".to_string(),
+            topic: "dataset-events".to_string(),
+            schema: "".to_string(),
+            subject_strategy,
+            format: EventFormat::Avro,
+            tombstones: TombstoneMode::None,
+            snapshot_topic: None,
+        }
+    }
+
+    #[test]
+    fn subject_follows_strategy() {
+        for (strategy, subject) in [
+            (SubjectStrategy::RecordName, "no.fdk.dataset.DatasetEvent"),
+            (SubjectStrategy::TopicName, "dataset-events-value"),
+            (
+                SubjectStrategy::TopicRecordName,
+                "dataset-events-no.fdk.dataset.DatasetEvent",
+            ),
+        ] {
+            let event_config = event_config(strategy);
+            assert_eq!(event_config.subject(), subject);
+            assert_eq!(
+                event_config.subject_name_strategy().get_subject().unwrap(),
+                subject
+            );
+        }
+    }
+
+    #[test]
+    fn snapshot_topic_is_registered_with_topic_strategies() {
+        for (strategy, subjects) in [
+            (
+                SubjectStrategy::RecordName,
+                vec!["no.fdk.dataset.DatasetEvent"],
+            ),
+            (
+                SubjectStrategy::TopicName,
+                vec!["dataset-events-value", "dataset-snapshots-value"],
+            ),
+            (
+                SubjectStrategy::TopicRecordName,
+                vec![
+                    "dataset-events-no.fdk.dataset.DatasetEvent",
+                    "dataset-snapshots-no.fdk.dataset.DatasetEvent",
+                ],
+            ),
+        ] {
+            let event_config = EventConfig {
+                snapshot_topic: Some("dataset-snapshots".to_string()),
+                ..event_config(strategy)
+            };
+            assert_eq!(event_config.subjects(), subjects);
+        }
+    }
+
+    fn change(id: &str, change: ChangeType, timestamp: i64) -> ResourceChange {
+        ResourceChange {
+            id: id.to_string(),
+            change,
+            timestamp,
+        }
+    }
+
+    fn collapse(changes: Vec<ResourceChange>) -> Vec<(String, ChangeType, i64)> {
+        collapse_changes(changes, "datasets")
+            .into_iter()
+            .map(|change| (change.id, change.change, change.timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn collapses_changes_within_report() {
+        let changes = collapse(vec![
+            change("a", ChangeType::CreateOrUpdate, 1),
+            change("b", ChangeType::CreateOrUpdate, 1),
+            change("a", ChangeType::CreateOrUpdate, 1),
+            change("b", ChangeType::Remove, 1),
+        ]);
+        assert_eq!(
+            changes,
+            [
+                ("a".to_string(), ChangeType::CreateOrUpdate, 1),
+                ("b".to_string(), ChangeType::Remove, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapses_changes_to_latest_report() {
+        let changes = collapse(vec![
+            change("a", ChangeType::Remove, 2),
+            change("b", ChangeType::Remove, 1),
+            change("a", ChangeType::CreateOrUpdate, 1),
+            change("b", ChangeType::CreateOrUpdate, 2),
+        ]);
+        assert_eq!(
+            changes,
+            [
+                ("a".to_string(), ChangeType::Remove, 2),
+                ("b".to_string(), ChangeType::CreateOrUpdate, 2),
+            ]
+        );
+    }
+
+    #[derive(Serialize)]
+    struct UnusedEvent;
+
+    impl kafka::Event for UnusedEvent {
+        fn key(&self) -> String {
+            String::new()
+        }
+    }
+
+    /// A resource whose removals cannot be listed, failing by the id of the removed catalog.
+    struct Unavailable;
+
+    #[async_trait]
+    impl Resource for Unavailable {
+        type Event = UnusedEvent;
+
+        async fn event(
+            _routing_key: &str,
+            _id: String,
+            _timestamp: i64,
+            _change: ChangeType,
+        ) -> Result<Option<Self::Event>, Error> {
+            Ok(None)
+        }
+
+        async fn removed_ids(_scope: RemovalScope, id: &str) -> Result<Vec<String>, Error> {
+            Err(match id {
+                "circuit-open" => HarvesterError::CircuitOpen(id.to_string()),
+                "server-error" => {
+                    HarvesterError::ServerError(reqwest::StatusCode::BAD_GATEWAY, String::new())
+                }
+                "timeout" => HarvesterError::Timeout(id.to_string()),
+                _ => HarvesterError::NotFound(id.to_string()),
+            }
+            .into())
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_report_on_transient_listing_errors() {
+        for (catalog, transient) in [
+            ("circuit-open", true),
+            ("server-error", true),
+            ("timeout", true),
+            ("not-found", false),
+        ] {
+            let report: HarvestReport = serde_json::from_value(serde_json::json!({
+                "startTime": "2022-02-11 11:30:06.110 +0100",
+                "changedResources": [],
+                "removedCatalogs": [{"fdkId": catalog}],
+            }))
+            .unwrap();
+
+            let result = report_changes::<Unavailable>(report).await;
+            match transient {
+                true => assert!(result.is_err_and(|e| e.is_transient()), "{}", catalog),
+                false => assert!(
+                    result.is_ok_and(|changes| changes.is_empty()),
+                    "{}",
+                    catalog
+                ),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn events_older_than_published_are_dropped() {
+        let harness = TestHarness::new(test_event_config("stale-events"));
+        stub_get("/test-resources/stale-1", 200, "<graph>");
+
+        let resource = serde_json::json!([{"fdkId": "stale-1"}]);
+        for (start_time, changed, removed) in [
+            (
+                "2022-02-11 11:30:06.110 +0100",
+                resource.clone(),
+                serde_json::json!([]),
+            ),
+            (
+                "2022-02-10 11:30:06.110 +0100",
+                serde_json::json!([]),
+                resource,
+            ),
+        ] {
+            let reports = serde_json::json!([{
+                "startTime": start_time,
+                "changedResources": changed,
+                "removedResources": removed,
+            }]);
+            harness
+                .deliver::<TestResource>("tests.harvested", &reports.to_string())
+                .await;
+        }
+
+        let records = harness.records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value["type"], "TEST_HARVESTED");
+    }
+
+    #[tokio::test]
+    async fn older_event_is_dropped_while_newer_is_published() {
+        let harness = TestHarness::new(test_event_config("concurrent-stale-events"));
+        stub_slow_get(
+            "/test-resources/concurrent-1",
+            200,
+            "<graph>",
+            Duration::from_millis(200),
+        );
+
+        let newer = serde_json::json!([{
+            "startTime": "2022-02-11 11:30:06.110 +0100",
+            "changedResources": [{"fdkId": "concurrent-1"}],
+            "removedResources": [],
+        }])
+        .to_string();
+        let older = serde_json::json!([{
+            "startTime": "2022-02-10 11:30:06.110 +0100",
+            "changedResources": [],
+            "removedResources": [{"fdkId": "concurrent-1"}],
+        }])
+        .to_string();
+        tokio::join!(
+            harness.deliver::<TestResource>("tests.harvested", &newer),
+            harness.deliver::<TestResource>("tests.harvested", &older),
+        );
+
+        let records = harness.records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value["type"], "TEST_HARVESTED");
+    }
+
+    #[tokio::test]
+    async fn removed_catalogs_and_data_sources_are_expanded() {
+        let harness = TestHarness::new(test_event_config("expanded-removal-events"));
+        stub_get(
+            "/test-resources/ids?catalogId=catalog-1",
+            200,
+            r#"["catalog-resource-1", "catalog-resource-2"]"#,
+        );
+        stub_get(
+            "/test-resources/ids?dataSourceId=source-1",
+            200,
+            r#"["source-resource-1"]"#,
+        );
+
+        let reports = serde_json::json!([{
+            "startTime": "2022-02-11 11:30:06.110 +0100",
+            "changedResources": [],
+            "removedCatalogs": [{"fdkId": "catalog-1"}],
+            "removedDataSources": [{"id": "source-1"}],
+        }]);
+        let ack = harness
+            .deliver::<TestResource>("tests.harvested", &reports.to_string())
+            .await;
+        assert_eq!(ack, Acknowledgement::Ack);
+
+        let records = harness.records().await.unwrap();
+        let ids = records
+            .iter()
+            .map(|record| record.value["fdkId"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                "catalog-resource-1",
+                "catalog-resource-2",
+                "source-resource-1"
+            ]
+        );
+        assert!(records
+            .iter()
+            .all(|record| record.value["type"] == "TEST_REMOVED"));
+    }
 }
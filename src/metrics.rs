@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
-use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry,
+};
 
 use crate::error::Error;
 
@@ -21,6 +24,200 @@ lazy_static! {
         tracing::error!(error = e.to_string(), "processing_time");
         std::process::exit(1);
     });
+    pub static ref PROCESSED_EVENTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("processed_events", "Processed Events"),
+        &["resource_type", "change", "outcome"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "processed_events metric error");
+        std::process::exit(1);
+    });
+    pub static ref HARVESTER_NOT_FOUND: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "harvester_not_found",
+            "Changed Resources Not Found in Harvester"
+        ),
+        &["action"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "harvester_not_found metric error");
+        std::process::exit(1);
+    });
+    pub static ref STALE_EVENTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("stale_events", "Events Older Than the Last Published"),
+        &["action"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "stale_events metric error");
+        std::process::exit(1);
+    });
+    pub static ref HARVESTER_FETCH_TIME: Histogram = Histogram::with_opts(HistogramOpts {
+        common_opts: Opts::new("harvester_fetch_time", "Harvester Fetch Times"),
+        buckets: vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+    })
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "harvester_fetch_time metric error");
+        std::process::exit(1);
+    });
+    pub static ref HARVESTER_WAIT_TIME: Histogram = Histogram::with_opts(HistogramOpts {
+        common_opts: Opts::new(
+            "harvester_wait_time",
+            "Time Waiting for Harvester Rate and Concurrency Limits"
+        ),
+        buckets: vec![0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0],
+    })
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "harvester_wait_time metric error");
+        std::process::exit(1);
+    });
+    pub static ref CIRCUIT_BREAKER_STATE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "circuit_breaker_state",
+            "Circuit Breaker State, 0 Closed, 1 Open and 2 Half-Open"
+        ),
+        &["circuit"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "circuit_breaker_state metric error");
+        std::process::exit(1);
+    });
+    pub static ref CIRCUIT_BREAKER_REJECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "circuit_breaker_rejections",
+            "Calls Rejected by Open Circuit Breakers"
+        ),
+        &["circuit"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(
+            error = e.to_string(),
+            "circuit_breaker_rejections metric error"
+        );
+        std::process::exit(1);
+    });
+    pub static ref ENCODE_TIME: Histogram = Histogram::with_opts(HistogramOpts {
+        common_opts: Opts::new("encode_time", "Event Encoding Times"),
+        buckets: vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0],
+    })
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "encode_time metric error");
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_DELIVERY_TIME: Histogram = Histogram::with_opts(HistogramOpts {
+        common_opts: Opts::new("kafka_delivery_time", "Kafka Delivery Times"),
+        buckets: vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0],
+    })
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "kafka_delivery_time metric error");
+        std::process::exit(1);
+    });
+    pub static ref GRAPH_SIZE: Histogram = Histogram::with_opts(HistogramOpts {
+        common_opts: Opts::new("graph_size", "Harvested Graph Sizes in Bytes"),
+        buckets: exponential_buckets(1024.0, 4.0, 8).unwrap_or_default(),
+    })
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "graph_size metric error");
+        std::process::exit(1);
+    });
+    pub static ref IN_FLIGHT_DELIVERIES: IntGauge = IntGauge::new(
+        "in_flight_deliveries",
+        "Deliveries Currently Being Processed"
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "in_flight_deliveries metric error");
+        std::process::exit(1);
+    });
+    pub static ref LAST_PUBLISH_TIMESTAMP: Gauge = Gauge::new(
+        "last_publish_timestamp",
+        "Unix Timestamp of the Last Successfully Published Event"
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "last_publish_timestamp metric error");
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_QUEUE_MESSAGES: IntGauge = IntGauge::new(
+        "kafka_producer_queue_messages",
+        "Messages in Kafka Producer Queues"
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(
+            error = e.to_string(),
+            "kafka_producer_queue_messages metric error"
+        );
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_QUEUE_BYTES: IntGauge = IntGauge::new(
+        "kafka_producer_queue_bytes",
+        "Total Size of Messages in Kafka Producer Queues"
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(
+            error = e.to_string(),
+            "kafka_producer_queue_bytes metric error"
+        );
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_BROKER_TX_ERRORS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "kafka_broker_tx_errors",
+            "Total Kafka Broker Transmission Errors"
+        ),
+        &["broker"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "kafka_broker_tx_errors metric error");
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_BROKER_TX_RETRIES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "kafka_broker_tx_retries",
+            "Total Kafka Broker Request Retries"
+        ),
+        &["broker"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(
+            error = e.to_string(),
+            "kafka_broker_tx_retries metric error"
+        );
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_BROKER_OUTBUF_MESSAGES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "kafka_broker_outbuf_messages",
+            "Messages Awaiting Transmission to Kafka Broker"
+        ),
+        &["broker"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(
+            error = e.to_string(),
+            "kafka_broker_outbuf_messages metric error"
+        );
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_BROKER_RTT: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "kafka_broker_rtt",
+            "Kafka Broker Round-Trip Times in Seconds"
+        ),
+        &["broker", "quantile"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "kafka_broker_rtt metric error");
+        std::process::exit(1);
+    });
+    pub static ref KAFKA_TOPIC_BATCH_SIZE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "kafka_topic_batch_size",
+            "Kafka Producer Batch Sizes in Bytes"
+        ),
+        &["topic", "quantile"]
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!(error = e.to_string(), "kafka_topic_batch_size metric error");
+        std::process::exit(1);
+    });
 }
 
 pub fn register_metrics() {
@@ -37,6 +234,172 @@ pub fn register_metrics() {
             tracing::error!(error = e.to_string(), "response_time collector error");
             std::process::exit(1);
         });
+
+    REGISTRY
+        .register(Box::new(PROCESSED_EVENTS.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "processed_events collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(STALE_EVENTS.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "stale_events collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(HARVESTER_NOT_FOUND.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "harvester_not_found collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(HARVESTER_FETCH_TIME.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "harvester_fetch_time collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(HARVESTER_WAIT_TIME.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "harvester_wait_time collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(CIRCUIT_BREAKER_STATE.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "circuit_breaker_state collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(CIRCUIT_BREAKER_REJECTIONS.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "circuit_breaker_rejections collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(ENCODE_TIME.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "encode_time collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_DELIVERY_TIME.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "kafka_delivery_time collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(GRAPH_SIZE.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "graph_size collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(IN_FLIGHT_DELIVERIES.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "in_flight_deliveries collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(LAST_PUBLISH_TIMESTAMP.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "last_publish_timestamp collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_QUEUE_MESSAGES.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_producer_queue_messages collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_QUEUE_BYTES.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_producer_queue_bytes collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_BROKER_TX_ERRORS.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_broker_tx_errors collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_BROKER_TX_RETRIES.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_broker_tx_retries collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_BROKER_OUTBUF_MESSAGES.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_broker_outbuf_messages collector error"
+            );
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_BROKER_RTT.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "kafka_broker_rtt collector error");
+            std::process::exit(1);
+        });
+
+    REGISTRY
+        .register(Box::new(KAFKA_TOPIC_BATCH_SIZE.clone()))
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                "kafka_topic_batch_size collector error"
+            );
+            std::process::exit(1);
+        });
 }
 
 pub fn get_metrics() -> Result<String, Error> {
@@ -0,0 +1,253 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+use crate::metrics::STALE_EVENTS;
+
+lazy_static! {
+    pub static ref STALE_EVENT_POLICY: StaleEventPolicy = env::var("STALE_EVENT_POLICY")
+        .map(|policy| StaleEventPolicy::from_name(&policy))
+        .unwrap_or(Ok(StaleEventPolicy::Drop))
+        .unwrap_or_else(|e| {
+            tracing::error!(error = e.to_string(), "stale event policy error");
+            std::process::exit(1);
+        });
+    pub static ref STALE_EVENT_CACHE_SIZE: usize = env::var("STALE_EVENT_CACHE_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(100000);
+    static ref PUBLISHED_TIMESTAMPS: Mutex<PublishedTimestamps> =
+        Mutex::new(PublishedTimestamps::new(*STALE_EVENT_CACHE_SIZE));
+}
+
+/// Timestamps of the last published event per resource, in memory of a single replica. Bounded
+/// by evicting the least recently published resources, which are then no longer guarded.
+struct PublishedTimestamps {
+    capacity: usize,
+    /// Timestamp and publish sequence number per resource.
+    timestamps: HashMap<String, (i64, u64)>,
+    /// Resources by publish sequence number, oldest first.
+    recency: BTreeMap<u64, String>,
+    sequence: u64,
+}
+
+impl PublishedTimestamps {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            timestamps: HashMap::new(),
+            recency: BTreeMap::new(),
+            sequence: 0,
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<i64> {
+        self.timestamps.get(id).map(|(timestamp, _)| *timestamp)
+    }
+
+    /// Keeps the latest timestamp of the resource, marking it as most recently published.
+    fn insert(&mut self, id: &str, timestamp: i64) {
+        self.sequence += 1;
+        let timestamp = match self.timestamps.get(id) {
+            Some((published, sequence)) => {
+                self.recency.remove(sequence);
+                timestamp.max(*published)
+            }
+            None => timestamp,
+        };
+        self.timestamps
+            .insert(id.to_string(), (timestamp, self.sequence));
+        self.recency.insert(self.sequence, id.to_string());
+
+        while self.timestamps.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, id)) => self.timestamps.remove(&id),
+                None => break,
+            };
+        }
+    }
+
+    /// Restores the timestamp a reservation replaced, unless a newer one was reserved since.
+    fn restore(&mut self, id: &str, timestamp: i64, previous: Option<i64>) {
+        let sequence = match self.timestamps.get(id) {
+            Some((reserved, sequence)) if *reserved == timestamp => *sequence,
+            _ => return,
+        };
+        match previous {
+            Some(previous) => {
+                self.timestamps.insert(id.to_string(), (previous, sequence));
+            }
+            None => {
+                self.timestamps.remove(id);
+                self.recency.remove(&sequence);
+            }
+        }
+    }
+}
+
+/// Timestamp reserved for an event while it is fetched and published, so an older event for the
+/// resource handled at the same time is stale. Dropping it before `commit` rolls the reservation
+/// back, as when fetching or publishing the event fails.
+#[must_use]
+pub struct Reservation {
+    id: String,
+    timestamp: i64,
+    /// Timestamp to restore on rollback, `None` when there is nothing to roll back.
+    rollback: Option<Option<i64>>,
+}
+
+impl Reservation {
+    /// Keeps the reserved timestamp, once the event is published.
+    pub fn commit(mut self) {
+        self.rollback = None;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(previous) = self.rollback.take() {
+            if let Ok(mut timestamps) = PUBLISHED_TIMESTAMPS.lock() {
+                timestamps.restore(&self.id, self.timestamp, previous);
+            }
+        }
+    }
+}
+
+/// How to handle an event older than the last one published for the resource.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StaleEventPolicy {
+    /// Skip the event.
+    Drop,
+    /// Publish the event, logging and counting it.
+    Flag,
+    /// Publish events without tracking timestamps.
+    Off,
+}
+
+impl StaleEventPolicy {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "drop" => Ok(Self::Drop),
+            "flag" => Ok(Self::Flag),
+            "off" => Ok(Self::Off),
+            _ => Err(format!("unknown stale event policy: '{}'", name)),
+        }
+    }
+
+    /// Reserves the timestamp of an event for the resource, checking it against the last published
+    /// or reserved in the same step. `None` when the event is stale and the policy drops it. Events
+    /// with the same timestamp, as when a report is redelivered, are not stale.
+    pub fn try_reserve(&self, id: &str, timestamp: i64) -> Option<Reservation> {
+        let mut reservation = Reservation {
+            id: id.to_string(),
+            timestamp,
+            rollback: None,
+        };
+        if *self == Self::Off {
+            return Some(reservation);
+        }
+        let mut timestamps = match PUBLISHED_TIMESTAMPS.lock() {
+            Ok(timestamps) => timestamps,
+            Err(_) => return Some(reservation),
+        };
+        match timestamps.get(id) {
+            Some(published) if timestamp < published => {
+                tracing::warn!(
+                    id,
+                    timestamp,
+                    published,
+                    policy = format!("{:?}", self),
+                    "event is older than the last published"
+                );
+                let action = match self {
+                    Self::Flag => "flagged",
+                    _ => "dropped",
+                };
+                STALE_EVENTS.with_label_values(&[action]).inc();
+                (*self == Self::Flag).then_some(reservation)
+            }
+            previous => {
+                timestamps.insert(id, timestamp);
+                reservation.rollback = Some(previous);
+                Some(reservation)
+            }
+        }
+    }
+
+    /// Records the timestamp of a published event, keeping the latest per resource.
+    pub fn published(&self, id: &str, timestamp: i64) {
+        if *self == Self::Off {
+            return;
+        }
+        if let Ok(mut timestamps) = PUBLISHED_TIMESTAMPS.lock() {
+            timestamps.insert(id, timestamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_events_older_than_published() {
+        let policy = StaleEventPolicy::Drop;
+        policy.try_reserve("drop-1", 2).unwrap().commit();
+        assert!(policy.try_reserve("drop-1", 2).is_some());
+        assert!(policy.try_reserve("drop-1", 1).is_none());
+
+        policy.published("drop-1", 1);
+        assert!(policy.try_reserve("drop-1", 1).is_none());
+    }
+
+    #[test]
+    fn drops_events_older_than_reserved() {
+        let policy = StaleEventPolicy::Drop;
+        let reservation = policy.try_reserve("reserve-1", 2).unwrap();
+        assert!(policy.try_reserve("reserve-1", 1).is_none());
+
+        drop(reservation);
+        assert!(policy.try_reserve("reserve-1", 1).is_some());
+    }
+
+    #[test]
+    fn rollback_keeps_newer_reservation() {
+        let policy = StaleEventPolicy::Drop;
+        policy.published("rollback-1", 1);
+        let older = policy.try_reserve("rollback-1", 2).unwrap();
+        policy.try_reserve("rollback-1", 3).unwrap().commit();
+
+        drop(older);
+        assert!(policy.try_reserve("rollback-1", 2).is_none());
+    }
+
+    #[test]
+    fn flags_and_publishes_stale_events() {
+        StaleEventPolicy::Flag.published("flag-1", 2);
+        assert!(StaleEventPolicy::Flag.try_reserve("flag-1", 1).is_some());
+    }
+
+    #[test]
+    fn evicts_least_recently_published() {
+        let mut timestamps = PublishedTimestamps::new(2);
+        timestamps.insert("a", 1);
+        timestamps.insert("b", 1);
+        timestamps.insert("a", 2);
+        timestamps.insert("c", 1);
+
+        assert_eq!(timestamps.get("a"), Some(2));
+        assert_eq!(timestamps.get("b"), None);
+        assert_eq!(timestamps.get("c"), Some(1));
+    }
+
+    #[test]
+    fn off_does_not_track_timestamps() {
+        StaleEventPolicy::Off.published("off-1", 2);
+        assert!(StaleEventPolicy::Drop.try_reserve("off-1", 1).is_some());
+        assert!(StaleEventPolicy::Off.try_reserve("off-1", 1).is_some());
+    }
+}
@@ -21,6 +21,10 @@ pub struct HarvestReport {
     pub changed_resources: Vec<HarvestReportChange>,
     #[serde(alias = "removedResources")]
     pub removed_resources: Option<Vec<HarvestReportChange>>,
+    #[serde(alias = "removedCatalogs")]
+    pub removed_catalogs: Option<Vec<HarvestReportChange>>,
+    #[serde(alias = "removedDataSources")]
+    pub removed_data_sources: Option<Vec<HarvestReportDataSource>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,6 +33,11 @@ pub struct HarvestReportChange {
     pub fdk_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct HarvestReportDataSource {
+    pub id: String,
+}
+
 fn var(key: &'static str) -> Result<String, RabbitError> {
     std::env::var(key).map_err(|e| RabbitError::ConfigError(key, e.to_string()))
 }
@@ -53,10 +62,12 @@ pub async fn connect() -> Result<Channel, RabbitError> {
     Ok(channel)
 }
 
+/// Declares and binds the queue, a temporary queue is deleted when the consumer disconnects.
 pub async fn setup(
     channel: &Channel,
     consumer_name: &str,
     routing_keys: &Vec<String>,
+    temporary: bool,
 ) -> Result<(), RabbitError> {
     channel
         .exchange_declare(
@@ -69,7 +80,11 @@ pub async fn setup(
     channel
         .queue_declare(
             consumer_name,
-            QueueDeclareOptions::default(),
+            QueueDeclareOptions {
+                exclusive: temporary,
+                auto_delete: temporary,
+                ..QueueDeclareOptions::default()
+            },
             FieldTable::default(),
         )
         .await?;
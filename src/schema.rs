@@ -1,32 +1,336 @@
+use std::{env, fs, time::Duration};
+
+use apache_avro::{schema::RecordField, Schema};
+use lazy_static::lazy_static;
+use protobuf::reflect::{FileDescriptor, MessageDescriptor};
+use protobuf_parse::Parser;
 use schema_registry_converter::{
-    async_impl::schema_registry::{post_schema, SrSettings},
+    async_impl::schema_registry::{get_schema_by_subject, post_schema, SrSettings},
     schema_registry_common::{SchemaType, SuppliedSchema},
 };
 
-use crate::{kafka::KafkaError, EventConfig};
+use crate::{
+    kafka::{EventFormat, KafkaError},
+    EventConfig,
+};
+
+lazy_static! {
+    pub static ref SCHEMA_REGISTRY_RETRIES: u32 = env::var("SCHEMA_REGISTRY_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3);
+    pub static ref SCHEMA_REGISTRY_RETRY_DELAY_MS: u64 = env::var("SCHEMA_REGISTRY_RETRY_DELAY_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1000);
+}
+
+/// File name the generated protobuf schema is parsed as.
+const PROTO_FILE: &str = "event.proto";
+
+/// Resolves the schema id events are encoded with, retrying while the registry is unavailable.
+/// A dry run only looks up the registered schema, otherwise the schema is registered.
+pub async fn resolve_schema_id(
+    sr_settings: &SrSettings,
+    event_config: &EventConfig,
+    dry_run: bool,
+) -> Result<u32, KafkaError> {
+    let mut attempt = 0;
+    loop {
+        let result = match dry_run {
+            true => lookup_schema(sr_settings, event_config).await,
+            false => setup_schema(sr_settings, event_config).await,
+        };
+        match result {
+            Err(e) if attempt < *SCHEMA_REGISTRY_RETRIES => {
+                attempt += 1;
+                tracing::warn!(
+                    attempt,
+                    error = e.to_string(),
+                    "schema registry unavailable, retrying"
+                );
+                tokio::time::sleep(Duration::from_millis(*SCHEMA_REGISTRY_RETRY_DELAY_MS)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Looks up the id of the schema registered for the event topic.
+pub async fn lookup_schema(
+    sr_settings: &SrSettings,
+    event_config: &EventConfig,
+) -> Result<u32, KafkaError> {
+    let schema = get_schema_by_subject(sr_settings, &event_config.subject_name_strategy()).await?;
+    Ok(schema.id)
+}
 
+/// Registers the event schema under each of its subjects, returning its id for the event topic.
 pub async fn setup_schema(
     sr_settings: &SrSettings,
     event_config: &EventConfig,
-) -> Result<(), KafkaError> {
-    tracing::info!(event_config.name, "registering schema");
-
-    let schema = post_schema(
-        sr_settings,
-        event_config.name.to_string(),
-        SuppliedSchema {
-            name: Some(event_config.name.to_string()),
-            schema_type: SchemaType::Avro,
-            schema: event_config.schema.to_string(),
-            references: vec![],
+) -> Result<u32, KafkaError> {
+    let mut schema_id = None;
+    for subject in event_config.subjects() {
+        tracing::info!(
+            event_config.name,
+            subject,
+            format = format!("{:?}", event_config.format),
+            "registering schema"
+        );
+
+        let schema =
+            post_schema(sr_settings, subject.clone(), supplied_schema(event_config)?).await?;
+
+        tracing::info!(
+            id = schema.id,
+            event_config.name,
+            subject,
+            "schema succesfully registered"
+        );
+        schema_id.get_or_insert(schema.id);
+    }
+    schema_id.ok_or_else(|| KafkaError::SchemaError("no schema subjects".to_string()))
+}
+
+/// The schema to register for the event format. JSON and protobuf schemas are generated from the
+/// Avro schema, so each event type only has one schema to maintain.
+pub fn supplied_schema(event_config: &EventConfig) -> Result<SuppliedSchema, KafkaError> {
+    let (schema_type, schema) = match event_config.format {
+        EventFormat::Avro => (SchemaType::Avro, event_config.schema.clone()),
+        EventFormat::Json => (SchemaType::Json, json_schema(&event_config.schema)?),
+        EventFormat::Protobuf => (SchemaType::Protobuf, proto_schema(&event_config.schema)?),
+    };
+    Ok(SuppliedSchema {
+        name: Some(event_config.name.to_string()),
+        schema_type,
+        schema,
+        references: vec![],
+    })
+}
+
+fn schema_error(message: String) -> KafkaError {
+    KafkaError::SchemaError(message)
+}
+
+/// Parses an Avro schema, which must be a record.
+fn avro_record(schema: &str) -> Result<(String, Vec<RecordField>), KafkaError> {
+    match Schema::parse_str(schema).map_err(|e| schema_error(e.to_string()))? {
+        Schema::Record(record) => Ok((record.name.fullname(None), record.fields)),
+        _ => Err(schema_error("schema must be an avro record".to_string())),
+    }
+}
+
+/// The schema of a field, and whether it is nullable.
+fn field_schema(field: &RecordField) -> Result<(&Schema, bool), KafkaError> {
+    match &field.schema {
+        Schema::Union(union) => match union.variants() {
+            [Schema::Null, schema] | [schema, Schema::Null] => Ok((schema, true)),
+            _ => Err(schema_error(format!(
+                "unsupported union in field '{}'",
+                field.name
+            ))),
         },
-    )
-    .await?;
-
-    tracing::info!(
-        id = schema.id,
-        event_config.name,
-        "schema succesfully registered"
-    );
-    Ok(())
+        schema => Ok((schema, false)),
+    }
+}
+
+/// Converts an Avro record schema to a JSON Schema.
+fn json_schema(schema: &str) -> Result<String, KafkaError> {
+    let (name, fields) = avro_record(schema)?;
+
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+    for field in &fields {
+        let (schema, nullable) = field_schema(field)?;
+        let property = match schema {
+            Schema::Boolean => serde_json::json!({"type": "boolean"}),
+            Schema::Int | Schema::Long | Schema::TimestampMillis => {
+                serde_json::json!({"type": "integer"})
+            }
+            Schema::Float | Schema::Double => serde_json::json!({"type": "number"}),
+            Schema::String => serde_json::json!({"type": "string"}),
+            Schema::Enum(schema) => serde_json::json!({"type": "string", "enum": schema.symbols}),
+            _ => {
+                return Err(schema_error(format!(
+                    "unsupported type in field '{}'",
+                    field.name
+                )))
+            }
+        };
+
+        let property = match nullable {
+            true => serde_json::json!({"oneOf": [{"type": "null"}, property]}),
+            false => {
+                required.push(field.name.clone());
+                property
+            }
+        };
+        properties.insert(field.name.clone(), property);
+    }
+
+    Ok(serde_json::json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": name,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+    .to_string())
+}
+
+/// Converts an Avro record schema to a proto3 schema, numbering fields in declaration order.
+fn proto_schema(schema: &str) -> Result<String, KafkaError> {
+    let (name, fields) = avro_record(schema)?;
+    let (package, message) = name.rsplit_once('.').unwrap_or(("", &name));
+
+    let mut enums = Vec::new();
+    let mut message_fields = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let (schema, nullable) = field_schema(field)?;
+        let field_type = match schema {
+            Schema::Boolean => "bool".to_string(),
+            Schema::Int => "int32".to_string(),
+            Schema::Long | Schema::TimestampMillis => "int64".to_string(),
+            Schema::Float => "float".to_string(),
+            Schema::Double => "double".to_string(),
+            Schema::String => "string".to_string(),
+            Schema::Enum(schema) => {
+                let values = schema
+                    .symbols
+                    .iter()
+                    .enumerate()
+                    .map(|(number, symbol)| format!("  {} = {};\n", symbol, number))
+                    .collect::<String>();
+                enums.push(format!("enum {} {{\n{}}}\n", schema.name.name, values));
+                schema.name.name.clone()
+            }
+            _ => {
+                return Err(schema_error(format!(
+                    "unsupported type in field '{}'",
+                    field.name
+                )))
+            }
+        };
+        message_fields.push(format!(
+            "  {}{} {} = {};\n",
+            if nullable { "optional " } else { "" },
+            field_type,
+            field.name,
+            index + 1
+        ));
+    }
+
+    let package = match package {
+        "" => "".to_string(),
+        package => format!("package {};\n\n", package),
+    };
+    Ok(format!(
+        "syntax = \"proto3\";\n\n{}{}\nmessage {} {{\n{}}}\n",
+        package,
+        enums.join("\n"),
+        message,
+        message_fields.concat()
+    ))
+}
+
+/// Descriptor of the event's protobuf message, used to encode events without generated code.
+pub fn proto_descriptor(event_config: &EventConfig) -> Result<MessageDescriptor, KafkaError> {
+    let schema = proto_schema(&event_config.schema)?;
+
+    // The parser only reads files, parse the schema from a temporary directory
+    let dir = tempfile::tempdir().map_err(|e| schema_error(e.to_string()))?;
+    let path = dir.path().join(PROTO_FILE);
+    fs::write(&path, schema).map_err(|e| schema_error(e.to_string()))?;
+    let file = Parser::new()
+        .pure()
+        .include(dir.path())
+        .input(&path)
+        .parse_and_typecheck()
+        .map_err(|e| schema_error(e.to_string()))?
+        .file_descriptors
+        .pop()
+        .ok_or_else(|| schema_error("protobuf schema has no file".to_string()))?;
+    let file = FileDescriptor::new_dynamic(file, &[]).map_err(|e| schema_error(e.to_string()))?;
+
+    file.message_by_full_name(&format!(".{}", event_config.name))
+        .or_else(|| file.message_by_full_name(&event_config.name))
+        .ok_or_else(|| {
+            schema_error(format!(
+                "protobuf schema has no message '{}'",
+                event_config.name
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kafka::{SubjectStrategy, TombstoneMode};
+
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "name": "DatasetEvent",
+        "namespace": "no.fdk.dataset",
+        "type": "record",
+        "fields": [
+            {
+                "name": "type",
+                "type": {
+                    "type": "enum",
+                    "name": "DatasetEventType",
+                    "symbols": ["DATASET_HARVESTED", "DATASET_REMOVED"]
+                }
+            },
+            {"name": "fdkId", "type": "string"},
+            {"name": "graph", "type": ["null", "string"]},
+            {"name": "timestamp", "type": "long", "logicalType": "timestamp-millis"}
+        ]
+    }"#;
+
+    #[test]
+    fn converts_to_json_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&json_schema(SCHEMA).unwrap()).unwrap();
+        assert_eq!(schema["title"], "no.fdk.dataset.DatasetEvent");
+        assert_eq!(
+            schema["properties"]["type"]["enum"],
+            serde_json::json!(["DATASET_HARVESTED", "DATASET_REMOVED"])
+        );
+        assert_eq!(schema["properties"]["timestamp"]["type"], "integer");
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["type", "fdkId", "timestamp"])
+        );
+    }
+
+    #[test]
+    fn converts_to_proto_schema() {
+        let schema = proto_schema(SCHEMA).unwrap();
+        assert!(schema.contains("package no.fdk.dataset;"));
+        assert!(schema.contains("enum DatasetEventType {\n  DATASET_HARVESTED = 0;\n"));
+        assert!(schema.contains("  DatasetEventType type = 1;\n"));
+        assert!(schema.contains("  optional string graph = 3;\n"));
+        assert!(schema.contains("  int64 timestamp = 4;\n"));
+    }
+
+    #[test]
+    fn parses_proto_descriptor() {
+        let event_config = EventConfig {
+            name: "no.fdk.dataset.DatasetEvent".to_string(),
+            topic: "dataset-events".to_string(),
+            schema: SCHEMA.to_string(),
+            subject_strategy: SubjectStrategy::RecordName,
+            format: EventFormat::Protobuf,
+            tombstones: TombstoneMode::None,
+            snapshot_topic: None,
+        };
+        let descriptor = proto_descriptor(&event_config).unwrap();
+        let fields = descriptor
+            .fields()
+            .map(|field| field.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(fields, ["type", "fdkId", "graph", "timestamp"]);
+    }
 }
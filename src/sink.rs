@@ -0,0 +1,248 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use rdkafka::{
+    message::{Header, OwnedHeaders},
+    producer::FutureRecord,
+};
+use serde::Serialize;
+
+use crate::{
+    kafka::{DeliveryReceipt, Producer, DRY_RUN, QUEUE_TIMEOUT_MS},
+    metrics::KAFKA_DELIVERY_TIME,
+    PRODUCER,
+};
+
+lazy_static! {
+    pub static ref EVENT_SINK: String = env::var("EVENT_SINK").unwrap_or("kafka".to_string());
+    pub static ref EVENT_SINK_PATH: Option<String> = env::var("EVENT_SINK_PATH")
+        .or_else(|_| env::var("DRY_RUN_OUTPUT"))
+        .ok();
+    pub static ref EVENT_SINK_URL: Option<String> = env::var("EVENT_SINK_URL").ok();
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error(transparent)]
+    RdkafkaError(#[from] rdkafka::error::KafkaError),
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+    #[error(transparent)]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("webhook responded with {0} - {1}")]
+    WebhookError(reqwest::StatusCode, String),
+    #[error("{0}")]
+    ConfigError(String),
+}
+
+/// An encoded event, ready to be written to a sink.
+#[derive(Clone, Debug, Serialize)]
+pub struct EventRecord {
+    pub topic: String,
+    pub key: Option<String>,
+    /// The event before encoding, for sinks writing JSON.
+    pub event: serde_json::Value,
+    /// The encoded event, as produced to Kafka, `None` for tombstones.
+    #[serde(skip)]
+    pub payload: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub headers: Vec<(String, String)>,
+}
+
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, record: EventRecord) -> Result<DeliveryReceipt, SinkError>;
+}
+
+/// Receipt for sinks without partitions and offsets.
+const NO_RECEIPT: DeliveryReceipt = DeliveryReceipt {
+    partition: -1,
+    offset: -1,
+};
+
+/// Creates the sink selected by `EVENT_SINK`, dry runs always write JSON lines.
+pub fn create_sink() -> Result<Box<dyn EventSink>, SinkError> {
+    let sink = match *DRY_RUN {
+        true => "jsonl",
+        false => EVENT_SINK.as_str(),
+    };
+
+    match sink {
+        "kafka" => Ok(Box::new(KafkaSink::new(&PRODUCER))),
+        "jsonl" => Ok(Box::new(JsonLinesSink::new(EVENT_SINK_PATH.as_deref())?)),
+        "webhook" => Ok(Box::new(WebhookSink::new(EVENT_SINK_URL.clone().ok_or(
+            SinkError::ConfigError("EVENT_SINK_URL is not set".to_string()),
+        )?)?)),
+        _ => Err(SinkError::ConfigError(format!(
+            "unknown event sink: '{}'",
+            sink
+        ))),
+    }
+}
+
+/// Produces records to Kafka.
+pub struct KafkaSink {
+    producer: &'static Producer,
+}
+
+impl KafkaSink {
+    pub fn new(producer: &'static Producer) -> Self {
+        Self { producer }
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn send(&self, record: EventRecord) -> Result<DeliveryReceipt, SinkError> {
+        let headers = record
+            .headers
+            .iter()
+            .fold(OwnedHeaders::new(), |headers, (key, value)| {
+                headers.insert(Header {
+                    key,
+                    value: Some(value),
+                })
+            });
+        let mut kafka_record = FutureRecord::<String, Vec<u8>>::to(&record.topic).headers(headers);
+        if let Some(key) = &record.key {
+            kafka_record = kafka_record.key(key);
+        }
+        if let Some(payload) = &record.payload {
+            kafka_record = kafka_record.payload(payload);
+        }
+
+        let start_time = Instant::now();
+        let (partition, offset) = self
+            .producer
+            .send(kafka_record, Duration::from_millis(*QUEUE_TIMEOUT_MS))
+            .await
+            .map_err(|e| e.0)?;
+        KAFKA_DELIVERY_TIME.observe(start_time.elapsed().as_secs_f64());
+
+        Ok(DeliveryReceipt { partition, offset })
+    }
+}
+
+/// Keeps records in memory, for tests.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Default)]
+pub struct MemorySink {
+    records: Mutex<Vec<EventRecord>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MemorySink {
+    pub fn records(&self) -> Vec<EventRecord> {
+        self.records
+            .lock()
+            .map(|records| records.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[async_trait]
+impl EventSink for MemorySink {
+    async fn send(&self, record: EventRecord) -> Result<DeliveryReceipt, SinkError> {
+        let mut records = self
+            .records
+            .lock()
+            .map_err(|e| SinkError::ConfigError(e.to_string()))?;
+        records.push(record);
+        Ok(DeliveryReceipt {
+            partition: 0,
+            offset: records.len() as i64 - 1,
+        })
+    }
+}
+
+/// Appends records as JSON lines to a file, or to stdout when no path is given.
+pub struct JsonLinesSink {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    #[serde(flatten)]
+    record: &'a EventRecord,
+    /// Hex encoded payload, null for tombstones.
+    payload: Option<String>,
+}
+
+impl JsonLinesSink {
+    pub fn new(path: Option<&str>) -> Result<Self, SinkError> {
+        let output: Box<dyn Write + Send> = match path {
+            Some(path) if path != "-" => {
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            _ => Box::new(io::stdout()),
+        };
+        Ok(Self {
+            output: Mutex::new(output),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for JsonLinesSink {
+    async fn send(&self, record: EventRecord) -> Result<DeliveryReceipt, SinkError> {
+        let line = serde_json::to_string(&JsonLine {
+            record: &record,
+            payload: record
+                .payload
+                .as_ref()
+                .map(|payload| payload.iter().map(|byte| format!("{:02x}", byte)).collect()),
+        })
+        .map_err(io::Error::from)?;
+
+        let mut output = self
+            .output
+            .lock()
+            .map_err(|e| SinkError::ConfigError(e.to_string()))?;
+        writeln!(output, "{}", line)?;
+        output.flush()?;
+        Ok(NO_RECEIPT)
+    }
+}
+
+/// Posts records as JSON to an HTTP endpoint, forwarding the trace headers.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> Result<Self, SinkError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self { client, url })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn send(&self, record: EventRecord) -> Result<DeliveryReceipt, SinkError> {
+        let body = serde_json::to_vec(&record).map_err(io::Error::from)?;
+        let request = record.headers.iter().fold(
+            self.client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json"),
+            |request, (key, value)| request.header(key, value),
+        );
+
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(SinkError::WebhookError(status, response.text().await?));
+        }
+        Ok(NO_RECEIPT)
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    env,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use lapin::{
+    message::{Delivery, DeliveryResult},
+    options::{BasicAckOptions, BasicNackOptions, BasicQosOptions},
+    types::AMQPValue,
+    Channel, Consumer,
+};
+use lazy_static::lazy_static;
+use rdkafka::{
+    consumer::{CommitMode, Consumer as _, StreamConsumer},
+    message::{BorrowedMessage, Headers},
+    ClientConfig, Message, Offset,
+};
+
+use crate::{
+    kafka::{BROKERS, DRY_RUN},
+    rabbit::{self, RabbitError},
+    ResourceConfig,
+};
+
+lazy_static! {
+    pub static ref REPORT_SOURCE: String =
+        env::var("REPORT_SOURCE").unwrap_or("rabbitmq".to_string());
+    pub static ref RABBITMQ_PREFETCH_COUNT: u16 = env::var("RABBITMQ_PREFETCH_COUNT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10);
+    pub static ref MAX_REDELIVERIES: u32 = env::var("MAX_REDELIVERIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10);
+    pub static ref KAFKA_SOURCE_OFFSET_RESET: String = env::var("KAFKA_SOURCE_OFFSET_RESET")
+        .unwrap_or_else(|_| match *DRY_RUN {
+            true => "latest".to_string(),
+            false => "earliest".to_string(),
+        });
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error(transparent)]
+    RabbitError(#[from] RabbitError),
+    #[error(transparent)]
+    RdkafkaError(#[from] rdkafka::error::KafkaError),
+    #[error("{0}")]
+    ConfigError(String),
+}
+
+/// A message with harvest reports, independent of the transport it was received with.
+#[derive(Debug)]
+pub struct ReportMessage {
+    pub routing_key: String,
+    pub data: Vec<u8>,
+    /// Message headers, with lowercase keys.
+    pub headers: HashMap<String, String>,
+    /// How many times the message has been requeued before.
+    pub redeliveries: u32,
+}
+
+/// What the source should do with a message after it has been handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Acknowledgement {
+    Ack,
+    /// Deliver the message again later.
+    Requeue,
+}
+
+/// Counts how many times messages have been requeued, for sources that do not count them.
+#[derive(Default)]
+struct RedeliveryCounter {
+    counts: Mutex<HashMap<u64, u32>>,
+}
+
+impl RedeliveryCounter {
+    fn key(message: impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        message.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn redeliveries(&self, key: u64) -> u32 {
+        let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        counts.get(&key).copied().unwrap_or(0)
+    }
+
+    /// Updates the count after a message has been handled.
+    fn handled(&self, key: u64, acknowledgement: Acknowledgement) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        match acknowledgement {
+            Acknowledgement::Ack => {
+                counts.remove(&key);
+            }
+            Acknowledgement::Requeue => *counts.entry(key).or_default() += 1,
+        }
+    }
+}
+
+pub type MessageHandler = Arc<
+    dyn Fn(ReportMessage) -> Pin<Box<dyn Future<Output = Acknowledgement> + Send>> + Send + Sync,
+>;
+
+#[async_trait]
+pub trait ReportSource: Send {
+    /// Consumes messages, calling the handler for each of them. Only returns if consuming fails.
+    async fn run(self: Box<Self>, handler: MessageHandler) -> Result<(), SourceError>;
+}
+
+/// Creates the source selected by `REPORT_SOURCE`. Dry runs consume with their own queue or
+/// consumer group, leaving the real one untouched.
+pub async fn create_source(
+    resource_config: &ResourceConfig,
+) -> Result<Box<dyn ReportSource>, SourceError> {
+    let consumer_name = match *DRY_RUN {
+        true => format!("{}-dry-run", resource_config.consumer_name),
+        false => resource_config.consumer_name.clone(),
+    };
+
+    match REPORT_SOURCE.as_str() {
+        "rabbitmq" => Ok(Box::new(
+            AmqpSource::connect(&consumer_name, &resource_config.routing_keys, *DRY_RUN).await?,
+        )),
+        "kafka" => Ok(Box::new(KafkaSource::new(
+            &consumer_name,
+            &resource_config.routing_keys,
+        )?)),
+        source => Err(SourceError::ConfigError(format!(
+            "unknown report source: '{}'",
+            source
+        ))),
+    }
+}
+
+/// Consumes harvest reports from the RabbitMQ `harvests` exchange.
+pub struct AmqpSource {
+    _channel: Channel,
+    consumer: Consumer,
+    redeliveries: Arc<RedeliveryCounter>,
+}
+
+impl AmqpSource {
+    pub async fn connect(
+        queue_name: &str,
+        routing_keys: &Vec<String>,
+        temporary: bool,
+    ) -> Result<Self, SourceError> {
+        let channel = rabbit::connect().await?;
+        rabbit::setup(&channel, queue_name, routing_keys, temporary).await?;
+        // Bounds the unacknowledged deliveries, so consumption pauses while messages wait
+        channel
+            .basic_qos(*RABBITMQ_PREFETCH_COUNT, BasicQosOptions::default())
+            .await
+            .map_err(RabbitError::from)?;
+        let consumer = rabbit::create_consumer(&channel, queue_name).await?;
+        Ok(Self {
+            _channel: channel,
+            consumer,
+            redeliveries: Arc::default(),
+        })
+    }
+}
+
+fn amqp_headers(delivery: &Delivery) -> HashMap<String, String> {
+    delivery
+        .properties
+        .headers()
+        .iter()
+        .flat_map(|headers| headers.inner().iter())
+        .filter_map(|(key, value)| {
+            let value = match value {
+                AMQPValue::LongString(s) => String::from_utf8(s.as_bytes().to_vec()).ok(),
+                AMQPValue::ShortString(s) => Some(s.to_string()),
+                _ => None,
+            }?;
+            Some((key.as_str().to_lowercase(), value))
+        })
+        .collect()
+}
+
+/// The `x-delivery-count` header set by quorum queues.
+fn amqp_delivery_count(delivery: &Delivery) -> Option<u32> {
+    let count = delivery
+        .properties
+        .headers()
+        .as_ref()?
+        .inner()
+        .get("x-delivery-count")?;
+    let count = match count {
+        AMQPValue::ShortShortUInt(count) => u64::from(*count),
+        AMQPValue::ShortUInt(count) => u64::from(*count),
+        AMQPValue::LongUInt(count) => u64::from(*count),
+        AMQPValue::LongLongInt(count) => u64::try_from(*count).ok()?,
+        AMQPValue::LongInt(count) => u64::try_from(*count).ok()?,
+        _ => return None,
+    };
+    u32::try_from(count).ok()
+}
+
+async fn receive_delivery(
+    handler: MessageHandler,
+    redeliveries: Arc<RedeliveryCounter>,
+    delivery: DeliveryResult,
+) {
+    let delivery = match delivery {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => return,
+        Err(error) => {
+            tracing::error!(error = error.to_string(), "failed to consume message");
+            std::process::exit(1);
+        }
+    };
+
+    // Classic queues only flag redeliveries, so those are counted here
+    let key = RedeliveryCounter::key((delivery.routing_key.as_str(), &delivery.data));
+    let redelivery_count = match amqp_delivery_count(&delivery) {
+        Some(count) => count,
+        None if delivery.redelivered => redeliveries.redeliveries(key),
+        None => 0,
+    };
+    let message = ReportMessage {
+        routing_key: delivery.routing_key.to_string(),
+        data: delivery.data.clone(),
+        headers: amqp_headers(&delivery),
+        redeliveries: redelivery_count,
+    };
+
+    let acknowledgement = handler(message).await;
+    redeliveries.handled(key, acknowledgement);
+    let result = match acknowledgement {
+        Acknowledgement::Ack => delivery.ack(BasicAckOptions::default()).await,
+        Acknowledgement::Requeue => {
+            delivery
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..BasicNackOptions::default()
+                })
+                .await
+        }
+    };
+    result.unwrap_or_else(|e| tracing::error!(error = e.to_string(), "failed to ack message"));
+}
+
+#[async_trait]
+impl ReportSource for AmqpSource {
+    async fn run(self: Box<Self>, handler: MessageHandler) -> Result<(), SourceError> {
+        let redeliveries = self.redeliveries.clone();
+        self.consumer.set_delegate(move |delivery| {
+            receive_delivery(handler.clone(), redeliveries.clone(), delivery)
+        });
+
+        // Deliveries are handled by the delegate, keep the channel open.
+        std::future::pending::<()>().await;
+        Ok(())
+    }
+}
+
+/// Consumes harvest reports from Kafka topics named after the routing keys. Offsets are committed
+/// manually once a message has been handled.
+pub struct KafkaSource {
+    consumer: StreamConsumer,
+}
+
+impl KafkaSource {
+    pub fn new(group_id: &str, topics: &[String]) -> Result<Self, SourceError> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", BROKERS.clone())
+            .set("group.id", group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", KAFKA_SOURCE_OFFSET_RESET.as_str())
+            .create()?;
+
+        let topics = topics.iter().map(String::as_str).collect::<Vec<_>>();
+        consumer.subscribe(&topics)?;
+        Ok(Self { consumer })
+    }
+}
+
+fn kafka_headers(message: &BorrowedMessage) -> HashMap<String, String> {
+    message
+        .headers()
+        .iter()
+        .flat_map(|headers| headers.iter())
+        .filter_map(|header| {
+            let value = std::str::from_utf8(header.value?).ok()?;
+            Some((header.key.to_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ReportSource for KafkaSource {
+    async fn run(self: Box<Self>, handler: MessageHandler) -> Result<(), SourceError> {
+        let redeliveries = RedeliveryCounter::default();
+        loop {
+            let message = self.consumer.recv().await?;
+            let key =
+                RedeliveryCounter::key((message.topic(), message.partition(), message.offset()));
+            let report_message = ReportMessage {
+                routing_key: message.topic().to_string(),
+                data: message.payload().unwrap_or_default().to_vec(),
+                headers: kafka_headers(&message),
+                redeliveries: redeliveries.redeliveries(key),
+            };
+
+            let acknowledgement = handler(report_message).await;
+            redeliveries.handled(key, acknowledgement);
+            match acknowledgement {
+                Acknowledgement::Ack => {
+                    if let Err(e) = self.consumer.commit_message(&message, CommitMode::Async) {
+                        tracing::error!(error = e.to_string(), "failed to commit offset");
+                    }
+                }
+                Acknowledgement::Requeue => {
+                    // Consume the same message again
+                    self.consumer.seek(
+                        message.topic(),
+                        message.partition(),
+                        Offset::Offset(message.offset()),
+                        Duration::from_secs(5),
+                    )?;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, env};
+
+use lazy_static::lazy_static;
+use opentelemetry::{global, propagation::Injector, trace::TracerProvider, Context};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+};
+
+lazy_static! {
+    // Standard OTLP exporter variables, traces are only exported when one of them is set.
+    static ref OTEL_EXPORTER_OTLP_ENDPOINT: Option<String> = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .or_else(|_| env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"))
+        .ok();
+    static ref OTEL_SERVICE_NAME: Option<String> = env::var("OTEL_SERVICE_NAME").ok();
+}
+
+/// Sets up JSON logging, and OTLP trace export if an OTLP endpoint is configured.
+pub fn init_tracing(service_name: &str) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = OTEL_EXPORTER_OTLP_ENDPOINT
+        .as_ref()
+        .map(|_| create_tracer_provider(service_name))
+        .transpose();
+
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .json()
+        .with_target(false)
+        .with_current_span(false)
+        .with_span_list(false)
+        .with_filter(LevelFilter::INFO);
+    let otel_layer = provider
+        .as_ref()
+        .ok()
+        .and_then(Option::as_ref)
+        .map(|provider| {
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer("fdk-kafka-event-publisher"))
+                .with_filter(LevelFilter::INFO)
+        });
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+
+    match provider {
+        Ok(Some(provider)) => {
+            global::set_tracer_provider(provider);
+            tracing::info!(
+                endpoint = OTEL_EXPORTER_OTLP_ENDPOINT.as_deref(),
+                "exporting traces"
+            );
+        }
+        Ok(None) => (),
+        Err(e) => {
+            tracing::error!(error = e.to_string(), "otlp exporter creation error");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn create_tracer_provider(service_name: &str) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = SpanExporter::builder().with_http().build()?;
+
+    let resource = Resource::builder()
+        .with_service_name(
+            OTEL_SERVICE_NAME
+                .clone()
+                .unwrap_or(service_name.to_string()),
+        )
+        .build();
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build())
+}
+
+/// Extracts the trace context propagated in the headers of a received message.
+pub(crate) fn parent_context(headers: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(headers))
+}
+
+struct HashMapInjector<'a>(&'a mut HashMap<String, String>);
+
+impl Injector for HashMapInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+/// Headers carrying the trace context of the current span.
+pub(crate) fn trace_headers() -> Vec<(String, String)> {
+    let context = tracing::Span::current().context();
+    let mut fields = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HashMapInjector(&mut fields))
+    });
+
+    fields.into_iter().collect()
+}
@@ -0,0 +1,327 @@
+//! In-process test harness, running the publisher pipeline against a stub harvester and schema
+//! registry and capturing the produced records in memory.
+
+use std::{
+    collections::HashMap,
+    env,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{http::StatusCode, web, App, HttpRequest, HttpResponse, HttpServer};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use schema_registry_converter::async_impl::{
+    avro::AvroDecoder, proto_raw::ProtoRawDecoder, schema_registry::SrSettings,
+};
+use serde::Serialize;
+
+use crate::{
+    encoder::{message_to_json, EventEncoder},
+    error::Error,
+    kafka::{self, EventFormat, SubjectStrategy, TombstoneMode},
+    receive_message,
+    schema::{proto_descriptor, supplied_schema},
+    sink::{EventRecord, MemorySink},
+    source::{Acknowledgement, ReportMessage},
+    utils::{http_get, list_ids},
+    ChangeType, EventConfig, RemovalScope, Resource,
+};
+
+lazy_static! {
+    static ref STUBS: Mutex<HashMap<String, (u16, String, Duration)>> = Mutex::new(HashMap::new());
+    static ref STUB_SERVER_URL: String = {
+        let url = start_stub_server().unwrap_or_else(|e| {
+            panic!("unable to start stub server: {}", e);
+        });
+        // Set once, as writing the environment is not safe while other tests read it
+        env::set_var("HARVESTER_API_URL", &url);
+        url
+    };
+    static ref SCHEMA_IDS: AtomicU32 = AtomicU32::new(1);
+}
+
+/// Starts a server on a dedicated thread, so it outlives the runtime of any single test.
+fn start_stub_server() -> Result<String, std::io::Error> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            HttpServer::new(|| App::new().default_service(web::to(stub_response)))
+                .workers(1)
+                .listen(listener)?
+                .run()
+                .await
+        })
+    });
+
+    Ok(url)
+}
+
+async fn stub_response(request: HttpRequest) -> HttpResponse {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_default();
+    let stub = STUBS.lock().ok().and_then(|stubs| stubs.get(path).cloned());
+
+    match stub {
+        Some((status, body, delay)) => {
+            tokio::time::sleep(delay).await;
+            HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::OK)).body(body)
+        }
+        None => HttpResponse::NotFound().body(format!("no stub for {}", path)),
+    }
+}
+
+/// Base URL of the stub server, serving both harvester and schema registry responses.
+/// `HARVESTER_API_URL` is pointed at it when it starts.
+pub fn stub_server_url() -> &'static str {
+    STUB_SERVER_URL.as_str()
+}
+
+/// Responds to GET requests for `path`, which includes the query string.
+pub fn stub_get(path: &str, status: u16, body: impl Into<String>) {
+    stub_slow_get(path, status, body, Duration::ZERO);
+}
+
+/// Responds to GET requests for `path` after `delay`. Starts the stub server, so stubs must be
+/// set up before resource code first reads `HARVESTER_API_URL`.
+pub fn stub_slow_get(path: &str, status: u16, body: impl Into<String>, delay: Duration) {
+    stub_server_url();
+    if let Ok(mut stubs) = STUBS.lock() {
+        stubs.insert(path.to_string(), (status, body.into(), delay));
+    }
+}
+
+/// A record captured by the harness, with the payload decoded to JSON, null for tombstones.
+#[derive(Debug)]
+pub struct TestRecord {
+    pub topic: String,
+    pub key: Option<String>,
+    pub value: serde_json::Value,
+}
+
+pub struct TestHarness {
+    event_config: EventConfig,
+    sink: MemorySink,
+    encoder: EventEncoder,
+    sr_settings: SrSettings,
+}
+
+impl TestHarness {
+    /// Creates a harness with the event schema registered in the stub schema registry.
+    ///
+    /// Starts the stub server, so the harness must be created before resource code first reads
+    /// `HARVESTER_API_URL`.
+    pub fn new(event_config: EventConfig) -> Self {
+        let id = SCHEMA_IDS.fetch_add(1, Ordering::SeqCst);
+        let supplied_schema = supplied_schema(&event_config).unwrap_or_else(|e| {
+            panic!("invalid event schema: {}", e);
+        });
+        let schema_type = match event_config.format {
+            EventFormat::Avro => "AVRO",
+            EventFormat::Json => "JSON",
+            EventFormat::Protobuf => "PROTOBUF",
+        };
+        let schema = serde_json::json!({
+            "subject": event_config.subject(),
+            "version": 1,
+            "id": id,
+            "schemaType": schema_type,
+            "schema": supplied_schema.schema,
+        })
+        .to_string();
+        stub_get(&format!("/schemas/ids/{}?deleted=true", id), 200, schema);
+
+        let sr_settings = SrSettings::new(stub_server_url().to_string());
+        let encoder = EventEncoder::new(&event_config, id).unwrap_or_else(|e| {
+            panic!("unable to create encoder: {}", e);
+        });
+        Self {
+            event_config,
+            sink: MemorySink::default(),
+            encoder,
+            sr_settings,
+        }
+    }
+
+    /// Runs a message with harvest reports through the pipeline, returning how it was acknowledged.
+    pub async fn deliver<R: Resource>(&self, routing_key: &str, reports: &str) -> Acknowledgement {
+        let message = ReportMessage {
+            routing_key: routing_key.to_string(),
+            data: reports.as_bytes().to_vec(),
+            headers: HashMap::new(),
+            redeliveries: 0,
+        };
+        receive_message::<R>(&self.sink, &self.encoder, &self.event_config, message).await
+    }
+
+    /// The raw records written to the sink.
+    pub fn raw_records(&self) -> Vec<EventRecord> {
+        self.sink.records()
+    }
+
+    /// The records written to the sink, with payloads decoded using the registered schema.
+    pub async fn records(&self) -> Result<Vec<TestRecord>, Error> {
+        let mut records = Vec::new();
+        for record in self.sink.records() {
+            let value = match &record.payload {
+                Some(payload) => self.decode(payload).await?,
+                None => serde_json::Value::Null,
+            };
+            records.push(TestRecord {
+                value,
+                topic: record.topic,
+                key: record.key,
+            });
+        }
+        Ok(records)
+    }
+
+    async fn decode(&self, payload: &[u8]) -> Result<serde_json::Value, Error> {
+        match self.event_config.format {
+            EventFormat::Avro => {
+                let decoded = AvroDecoder::new(self.sr_settings.clone())
+                    .decode(Some(payload))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(serde_json::Value::try_from(decoded.value).map_err(|e| e.to_string())?)
+            }
+            EventFormat::Json => Ok(serde_json::from_slice(
+                payload.get(5..).unwrap_or_default(),
+            )?),
+            EventFormat::Protobuf => {
+                let decoded = ProtoRawDecoder::new(self.sr_settings.clone())
+                    .decode(Some(payload))
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .ok_or("empty protobuf payload")?;
+                let message = proto_descriptor(&self.event_config)?
+                    .parse_from_bytes(&decoded.bytes)
+                    .map_err(|e| e.to_string())?;
+                Ok(message_to_json(message.as_ref()))
+            }
+        }
+    }
+}
+
+/// Harvest reports with one report changing and removing the given resources, started at
+/// 1644575406110.
+pub fn report(changed: &[&str], removed: &[&str]) -> String {
+    let ids = |ids: &[&str]| {
+        ids.iter()
+            .map(|id| serde_json::json!({"fdkId": id}))
+            .collect::<Vec<_>>()
+    };
+    serde_json::json!([{
+        "startTime": "2022-02-11 11:30:06.110 +0100",
+        "changedResources": ids(changed),
+        "removedResources": ids(removed),
+    }])
+    .to_string()
+}
+
+/// Event config for `TestResource`, with the subject named after the topic.
+pub fn test_event_config(topic: &str) -> EventConfig {
+    EventConfig {
+        name: "no.fdk.test.TestEvent".to_string(),
+        topic: topic.to_string(),
+        schema: r#"{
+            "name": "TestEvent",
+            "namespace": "no.fdk.test",
+            "type": "record",
+            "fields": [
+                {
+                    "name": "type",
+                    "type": {
+                        "type": "enum",
+                        "name": "TestEventType",
+                        "symbols": ["TEST_HARVESTED", "TEST_REMOVED"]
+                    }
+                },
+                {"name": "fdkId", "type": "string"},
+                {"name": "graph", "type": "string"},
+                {"name": "timestamp", "type": "long", "logicalType": "timestamp-millis"}
+            ]
+        }"#
+        .to_string(),
+        subject_strategy: SubjectStrategy::TopicName,
+        format: EventFormat::Avro,
+        tombstones: TombstoneMode::None,
+        snapshot_topic: None,
+    }
+}
+
+/// A resource served by the stub harvester, at `/test-resources/<fdkId>`.
+pub struct TestResource;
+
+#[async_trait]
+impl Resource for TestResource {
+    type Event = TestEvent;
+
+    async fn event(
+        _routing_key: &str,
+        id: String,
+        timestamp: i64,
+        change: ChangeType,
+    ) -> Result<Option<Self::Event>, Error> {
+        let (event_type, graph) = match change {
+            ChangeType::CreateOrUpdate => (
+                TestEventType::Harvested,
+                http_get(format!("{}/test-resources/{}", stub_server_url(), id)).await?,
+            ),
+            ChangeType::Remove => (TestEventType::Removed, "".to_string()),
+        };
+        Ok(Some(TestEvent {
+            event_type,
+            fdk_id: id,
+            graph,
+            timestamp,
+        }))
+    }
+
+    async fn removed_ids(scope: RemovalScope, id: &str) -> Result<Vec<String>, Error> {
+        list_ids(format!(
+            "{}/test-resources/ids?{}={}",
+            stub_server_url(),
+            scope.query_param(),
+            id
+        ))
+        .await
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestEvent {
+    #[serde(rename = "type")]
+    pub event_type: TestEventType,
+    #[serde(rename = "fdkId")]
+    pub fdk_id: String,
+    pub graph: String,
+    pub timestamp: i64,
+}
+
+impl kafka::Event for TestEvent {
+    fn key(&self) -> String {
+        self.fdk_id.clone()
+    }
+
+    fn is_removal(&self) -> bool {
+        matches!(self.event_type, TestEventType::Removed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum TestEventType {
+    #[serde(rename = "TEST_HARVESTED")]
+    Harvested,
+    #[serde(rename = "TEST_REMOVED")]
+    Removed,
+}
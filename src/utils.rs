@@ -1,26 +1,219 @@
-use lazy_static::lazy_static;
-use reqwest::StatusCode;
+use crate::{
+    error::Error,
+    harvester::{self, HarvesterError},
+    kafka::{OwnerIds, KEY_STRATEGY},
+};
 
-use crate::error::Error;
+/// Predicates as written in the harvester's Turtle, prefixed and as full IRI.
+const DCT_IS_PART_OF: [&str; 2] = ["dct:isPartOf", "<http://purl.org/dc/terms/isPartOf>"];
+const DCT_PUBLISHER: [&str; 2] = ["dct:publisher", "<http://purl.org/dc/terms/publisher>"];
+const FOAF_PRIMARY_TOPIC: [&str; 2] = [
+    "foaf:primaryTopic",
+    "<http://xmlns.com/foaf/0.1/primaryTopic>",
+];
 
-lazy_static! {
-    static ref CLIENT: reqwest::Client =
-        reqwest::ClientBuilder::new().build().unwrap_or_else(|e| {
-            tracing::error!(error = e.to_string(), "reqwest client creation error");
-            std::process::exit(1);
-        });
+pub async fn http_get(url: String) -> Result<String, Error> {
+    Ok(harvester::get(url).await?)
 }
 
-pub async fn http_get(url: String) -> Result<String, Error> {
-    let response = CLIENT.get(url).send().await?;
-
-    match response.status() {
-        StatusCode::OK => Ok(response.text().await?),
-        _ => Err(format!(
-            "Invalid http response: {} - {}",
-            response.status(),
-            response.text().await?
-        )
-        .into()),
+/// Fetches a JSON list of resource ids.
+pub async fn list_ids(url: String) -> Result<Vec<String>, Error> {
+    Ok(serde_json::from_str(&harvester::get(url).await?)?)
+}
+
+/// Splits a Turtle graph into statements, ignoring dots within IRIs, literals and comments.
+fn statements(graph: &str) -> Vec<&str> {
+    let bytes = graph.as_bytes();
+    let mut statements = Vec::new();
+    let (mut start, mut index) = (0, 0);
+    let (mut in_iri, mut in_comment, mut quote) = (false, false, None);
+    while index < bytes.len() {
+        let byte = bytes[index];
+        match quote {
+            Some(_) if byte == b'\\' => index += 1,
+            Some(q) if byte == q => quote = None,
+            Some(_) => {}
+            None if in_iri => in_iri = byte != b'>',
+            None if in_comment => in_comment = byte != b'\n',
+            None => match byte {
+                b'<' => in_iri = true,
+                b'#' => in_comment = true,
+                b'"' | b'\'' => quote = Some(byte),
+                b'.' if bytes
+                    .get(index + 1)
+                    .is_none_or(|next| next.is_ascii_whitespace()) =>
+                {
+                    statements.push(graph[start..index].trim());
+                    start = index + 1;
+                }
+                _ => {}
+            },
+        }
+        index += 1;
+    }
+    statements
+}
+
+/// The IRI of a statement's subject, without angle brackets.
+fn subject_iri(statement: &str) -> Option<&str> {
+    let subject = statement.strip_prefix('<')?;
+    Some(&subject[..subject.find('>')?])
+}
+
+/// The IRI object of `predicate` in a statement, without angle brackets.
+fn object_iri<'a>(statement: &'a str, predicate: [&str; 2]) -> Option<&'a str> {
+    predicate.iter().find_map(|predicate| {
+        statement.match_indices(predicate).find_map(|(index, _)| {
+            let object = statement[index + predicate.len()..]
+                .trim_start()
+                .strip_prefix('<')?;
+            Some(&object[..object.find('>')?])
+        })
+    })
+}
+
+/// The last path segment of an IRI. Catalog records end with the fdkId of their resource or
+/// catalog, and publishers end with their organization number.
+fn iri_id(iri: &str) -> Option<&str> {
+    iri.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|id| !id.is_empty())
+}
+
+/// Reads the catalog and publisher of a resource from its harvested graph. The catalog is taken
+/// from the resource's own catalog record, the one ending with its fdkId, and the publisher from
+/// the record's primary topic, so the ids of other resources in the graph are never used.
+fn graph_owner_ids(graph: &str, fdk_id: &str) -> OwnerIds {
+    let statements = statements(graph);
+    let records = statements
+        .iter()
+        .filter(|statement| subject_iri(statement).and_then(iri_id) == Some(fdk_id))
+        .collect::<Vec<_>>();
+    let catalog_id = records
+        .iter()
+        .find_map(|record| object_iri(record, DCT_IS_PART_OF).and_then(iri_id));
+    let publisher_id = records
+        .iter()
+        .find_map(|record| object_iri(record, FOAF_PRIMARY_TOPIC))
+        .and_then(|topic| {
+            statements
+                .iter()
+                .filter(|statement| subject_iri(statement) == Some(topic))
+                .find_map(|statement| object_iri(statement, DCT_PUBLISHER))
+        })
+        .and_then(iri_id);
+
+    OwnerIds {
+        catalog_id: catalog_id.map(String::from),
+        publisher_id: publisher_id.map(String::from),
+    }
+}
+
+/// Reads the catalog and publisher of a harvested resource from its graph, when the key strategy
+/// needs them, sparing the scan of large graphs otherwise.
+pub fn owner_ids(graph: &str, fdk_id: &str) -> OwnerIds {
+    match KEY_STRATEGY.uses_owner_ids() {
+        true => graph_owner_ids(graph, fdk_id),
+        false => OwnerIds::default(),
+    }
+}
+
+/// Looks up the catalog and publisher of a removed resource from its last harvested graph, when
+/// the key strategy needs them. A resource no longer in the harvester has none.
+pub async fn removed_owner_ids(url: String, fdk_id: &str) -> Result<OwnerIds, Error> {
+    if !KEY_STRATEGY.uses_owner_ids() {
+        return Ok(OwnerIds::default());
+    }
+    match harvester::get(url).await {
+        Ok(graph) => Ok(graph_owner_ids(&graph, fdk_id)),
+        Err(HarvesterError::NotFound(url)) => {
+            tracing::warn!(
+                id = fdk_id,
+                url,
+                "removed resource not found, keying removal by fdkId"
+            );
+            Ok(OwnerIds::default())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAPH: &str = r#"
+@prefix dct:  <http://purl.org/dc/terms/> .
+@prefix dcat: <http://www.w3.org/ns/dcat#> .
+@prefix foaf: <http://xmlns.com/foaf/0.1/> .
+
+<https://datasets.fellesdatakatalog.digdir.no/datasets/999>
+        a                  dcat:CatalogRecord ;
+        dct:isPartOf       <https://datasets.fellesdatakatalog.digdir.no/catalogs/111> ;
+        foaf:primaryTopic  <https://example.org/other> .
+
+<https://example.org/other>
+        dct:publisher  <https://data.brreg.no/enhetsregisteret/api/enheter/222> .
+
+<https://datasets.fellesdatakatalog.digdir.no/datasets/123>
+        a                  dcat:CatalogRecord ;
+        dct:identifier     "123. Not the end of the statement" ;
+        dct:isPartOf       <https://datasets.fellesdatakatalog.digdir.no/catalogs/456> ;
+        foaf:primaryTopic  <https://example.org/dataset> .
+
+<https://example.org/dataset>
+        a              dcat:Dataset ;
+        dct:title      "Dataset. With dots." ;
+        dct:publisher  <https://data.brreg.no/enhetsregisteret/api/enheter/974760673/> .
+"#;
+
+    fn expected_ids(catalog_id: Option<&str>, publisher_id: Option<&str>) -> OwnerIds {
+        OwnerIds {
+            catalog_id: catalog_id.map(String::from),
+            publisher_id: publisher_id.map(String::from),
+        }
+    }
+
+    #[test]
+    fn finds_owner_ids_of_resource() {
+        assert_eq!(
+            graph_owner_ids(GRAPH, "123"),
+            expected_ids(Some("456"), Some("974760673"))
+        );
+        assert_eq!(
+            graph_owner_ids(GRAPH, "999"),
+            expected_ids(Some("111"), Some("222"))
+        );
+    }
+
+    #[test]
+    fn skips_owner_ids_unless_key_strategy_uses_them() {
+        assert!(!KEY_STRATEGY.uses_owner_ids());
+        assert_eq!(owner_ids(GRAPH, "123"), OwnerIds::default());
+    }
+
+    #[test]
+    fn finds_full_iri_predicates() {
+        let graph = "<https://host/datasets/123> <http://purl.org/dc/terms/isPartOf> <https://host/catalogs/456> ;
+            <http://xmlns.com/foaf/0.1/primaryTopic> <https://example.org/dataset> .
+            <https://example.org/dataset> <http://purl.org/dc/terms/publisher> <https://example.org/orgs/789> .";
+        assert_eq!(
+            graph_owner_ids(graph, "123"),
+            expected_ids(Some("456"), Some("789"))
+        );
+    }
+
+    #[test]
+    fn ignores_other_resources_and_blank_node_objects() {
+        assert_eq!(graph_owner_ids("", "123"), OwnerIds::default());
+        assert_eq!(graph_owner_ids(GRAPH, "456"), OwnerIds::default());
+        assert_eq!(
+            graph_owner_ids(
+                "<https://host/datasets/123> dct:isPartOf [ a dcat:Catalog ] .",
+                "123"
+            ),
+            OwnerIds::default()
+        );
     }
 }